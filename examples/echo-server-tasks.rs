@@ -1,8 +1,11 @@
 use anyhow::bail;
-use async_http_codec::{BodyDecode, ResponseHeadEncoder};
+use async_http_codec::ResponseHead;
+use async_io::Timer;
 use async_web_server::tcp::{TcpIncoming, TcpStream};
-use async_ws::connection::{WsConfig, WsConnection, WsMessageReader, WsSend};
-use async_ws::http::{is_upgrade_request, upgrade_response};
+use async_ws::connection::{WsConfig, WsMessageReader, WsSend};
+use async_ws::extension::DeflateExtension;
+use async_ws::http::{is_upgrade_request, HandshakePolicy};
+use async_ws::server::accept;
 use futures::executor::{LocalPool, LocalSpawner};
 use futures::prelude::*;
 use futures::task::{LocalSpawnExt, SpawnExt};
@@ -10,9 +13,13 @@ use http::{HeaderValue, Request, Response};
 use log::LevelFilter;
 use simple_logger::SimpleLogger;
 use std::net::Ipv4Addr;
+use std::time::Duration;
 
 const CLIENT_HTML: &str = include_str!("./echo-client.html");
 
+// Echoes messages in separate tasks. Upgrade requests are left unread for `accept` to handle,
+// other requests get the client page.
+
 fn main() {
     SimpleLogger::new()
         .with_level(LevelFilter::Info)
@@ -22,19 +29,21 @@ fn main() {
     let mut pool = LocalPool::new();
     let spawner = pool.spawner();
     pool.run_until(async move {
-        let mut http_incoming = TcpIncoming::bind((Ipv4Addr::UNSPECIFIED, 8080))
-            .unwrap()
-            .http();
-        while let Some(request) = http_incoming.next().await {
+        let mut tcp_incoming = TcpIncoming::bind((Ipv4Addr::UNSPECIFIED, 8080)).unwrap();
+        while let Some(transport) = tcp_incoming.next().await {
             let spawner_clone = spawner.clone();
             spawner
                 .spawn_local(async move {
-                    if is_upgrade_request(&request) {
+                    let (request, head_len) = match peek_request(&transport).await {
+                        Ok(x) => x,
+                        Err(err) => return log::error!("http request error: {:?}", err),
+                    };
+                    if is_upgrade_request(&request).is_ok() {
                         log::info!("upgrade request received");
-                        let result = ws_handler(request, spawner_clone).await;
+                        let result = ws_handler(transport, spawner_clone).await;
                         log::info!("connection closed: {:?}", result)
                     } else {
-                        log::info!("serve html: {:?}", serve_html(request).await);
+                        log::info!("serve html: {:?}", serve_html(transport, head_len).await);
                     }
                 })
                 .unwrap()
@@ -42,34 +51,54 @@ fn main() {
     })
 }
 
-async fn serve_html(request: Request<BodyDecode<TcpStream>>) -> anyhow::Result<()> {
-    let resp_head = Response::builder()
+// Parses the request head without consuming it. Returns the request and the length of its head.
+async fn peek_request(transport: &TcpStream) -> anyhow::Result<(Request<()>, usize)> {
+    let mut buffer = vec![0u8; 8192];
+    loop {
+        let n = transport.peek(&mut buffer).await?;
+        if n == 0 {
+            bail!("connection closed before the request head was complete");
+        }
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut head = httparse::Request::new(&mut headers);
+        if let httparse::Status::Complete(len) = head.parse(&buffer[..n])? {
+            let mut request = Request::builder()
+                .method(head.method.unwrap())
+                .uri(head.path.unwrap());
+            for header in head.headers.iter() {
+                request = request.header(header.name, header.value);
+            }
+            return Ok((request.body(())?, len));
+        }
+        if n == buffer.len() {
+            bail!("request head too large");
+        }
+        // Peeking doesn't wait for more data, so give the rest of the head time to arrive.
+        Timer::after(Duration::from_millis(10)).await;
+    }
+}
+
+async fn serve_html(mut transport: TcpStream, head_len: usize) -> anyhow::Result<()> {
+    transport.read_exact(&mut vec![0u8; head_len]).await?;
+    let response = Response::builder()
         .header("Content-Length", HeaderValue::from(CLIENT_HTML.len()))
         .header("Connection", HeaderValue::from_static("close"))
-        .body(())?
-        .into_parts()
-        .0;
-    let (_request_head, body) = request.into_parts();
-    let mut transport = body.checkpoint().0;
-    ResponseHeadEncoder::default()
-        .encode(&mut transport, resp_head)
+        .body(())?;
+    ResponseHead::ref_response(&response)
+        .encode(&mut transport)
         .await?;
     transport.write_all(CLIENT_HTML.as_ref()).await?;
     transport.close().await?;
     Ok(())
 }
 
-async fn ws_handler(
-    request: Request<BodyDecode<TcpStream>>,
-    spawner: LocalSpawner,
-) -> anyhow::Result<()> {
-    let resp_head = upgrade_response(&request).unwrap().into_parts().0;
-    let (_request_head, body) = request.into_parts();
-    let mut transport = body.checkpoint().0;
-    ResponseHeadEncoder::default()
-        .encode(&mut transport, resp_head)
-        .await?;
-    let mut ws = WsConnection::with_config(transport, WsConfig::server());
+async fn ws_handler(transport: TcpStream, spawner: LocalSpawner) -> anyhow::Result<()> {
+    let mut config = WsConfig::server();
+    config.extensions = vec![Box::new(DeflateExtension::default())];
+    // The echo server doesn't rely on cookies, so any page may connect.
+    let policy = HandshakePolicy::allow_any_origin();
+    let (mut ws, _) = accept(transport, config, &policy, |_, _| Ok(())).await?;
+    log::info!("websocket opened at {}", ws.handshake().unwrap().path());
     while let Some(reader) = ws.next().await {
         log::info!("new {:?} message", reader.kind());
         let ws_send = ws.send(reader.kind());
@@ -82,7 +111,7 @@ async fn ws_handler(
             .unwrap()
     }
     match ws.err() {
-        None => log::info!("websocket closed without error"),
+        None => log::info!("websocket closed: {:?}", ws.close_status()),
         Some(err) => log::error!("websocket closed with error: {:?}", err),
     }
     Ok(())
@@ -1,7 +1,10 @@
-use async_http_codec::{BodyDecode, ResponseHeadEncoder};
+use async_http_codec::{RequestHead, ResponseHead};
 use async_web_server::tcp::{TcpIncoming, TcpStream};
 use async_ws::connection::{WsConfig, WsConnection};
-use async_ws::http::{is_upgrade_request, upgrade_response};
+use async_ws::extension::DeflateExtension;
+use async_ws::http::{
+    is_upgrade_request, negotiate_extensions, UpgradeResponseBuilder, WsHandshakeInfo,
+};
 use futures::executor::LocalPool;
 use futures::prelude::*;
 use futures::task::LocalSpawnExt;
@@ -21,18 +24,21 @@ fn main() {
     let mut pool = LocalPool::new();
     let spawner = pool.spawner();
     pool.run_until(async move {
-        let mut http_incoming = TcpIncoming::bind((Ipv4Addr::UNSPECIFIED, 8080))
-            .unwrap()
-            .http();
-        while let Some(request) = http_incoming.next().await {
+        let mut tcp_incoming = TcpIncoming::bind((Ipv4Addr::UNSPECIFIED, 8080)).unwrap();
+        while let Some(transport) = tcp_incoming.next().await {
             spawner
                 .spawn_local(async move {
-                    if is_upgrade_request(&request) {
+                    let (transport, request_head) = match RequestHead::decode(transport).await {
+                        Ok(x) => x,
+                        Err(err) => return log::error!("http request error: {:?}", err),
+                    };
+                    let request = Request::from(request_head);
+                    if is_upgrade_request(&request).is_ok() {
                         log::info!("upgrade request received");
-                        let result = ws_handler(request).await;
+                        let result = ws_handler(transport, request).await;
                         log::info!("connection closed: {:?}", result)
                     } else {
-                        log::info!("serve html: {:?}", serve_html(request).await);
+                        log::info!("serve html: {:?}", serve_html(transport).await);
                     }
                 })
                 .unwrap()
@@ -40,31 +46,33 @@ fn main() {
     })
 }
 
-async fn serve_html(request: Request<BodyDecode<TcpStream>>) -> anyhow::Result<()> {
-    let resp_head = Response::builder()
+async fn serve_html(mut transport: TcpStream) -> anyhow::Result<()> {
+    let response = Response::builder()
         .header("Content-Length", HeaderValue::from(CLIENT_HTML.len()))
         .header("Connection", HeaderValue::from_static("close"))
-        .body(())?
-        .into_parts()
-        .0;
-    let (_request_head, body) = request.into_parts();
-    let mut transport = body.checkpoint().0;
-    ResponseHeadEncoder::default()
-        .encode(&mut transport, resp_head)
+        .body(())?;
+    ResponseHead::ref_response(&response)
+        .encode(&mut transport)
         .await?;
     transport.write_all(CLIENT_HTML.as_ref()).await?;
     transport.close().await?;
     Ok(())
 }
 
-async fn ws_handler(request: Request<BodyDecode<TcpStream>>) -> anyhow::Result<()> {
-    let resp_head = upgrade_response(&request).unwrap().into_parts().0;
-    let (_request_head, body) = request.into_parts();
-    let mut transport = body.checkpoint().0;
-    ResponseHeadEncoder::default()
-        .encode(&mut transport, resp_head)
+async fn ws_handler(mut transport: TcpStream, request: Request<()>) -> anyhow::Result<()> {
+    let (extensions, header) =
+        negotiate_extensions(&request, vec![Box::new(DeflateExtension::default())]);
+    let response = UpgradeResponseBuilder::new(&request)?
+        .extensions(header)
+        .build()?;
+    let mut config = WsConfig::server();
+    config.extensions = extensions;
+    ResponseHead::ref_response(&response)
+        .encode(&mut transport)
         .await?;
-    let mut ws = WsConnection::with_config(transport, WsConfig::server());
+    let handshake = WsHandshakeInfo::new(&request, &response);
+    let mut ws = WsConnection::with_config(transport, config).with_handshake(handshake);
+    log::info!("websocket opened at {}", ws.handshake().unwrap().path());
     while let Some(mut reader) = ws.next().await {
         let mut writer = match ws.send(reader.kind()).await {
             None => break,
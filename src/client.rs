@@ -0,0 +1,67 @@
+use crate::connection::{WsConfig, WsConnection};
+use crate::http::codec::{parse_response, read_head, write_request, HeadError};
+use crate::http::{
+    check_upgrade_response, extension_offer, is_upgrade_request, response_extensions,
+    HandshakeError, WsHandshakeInfo,
+};
+use futures::prelude::*;
+use http::{Request, Response};
+use std::io;
+
+// Maximum size of the response head read by `connect`.
+pub const MAX_RESPONSE_HEAD_LEN: usize = 16 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConnectError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("response head exceeds {MAX_RESPONSE_HEAD_LEN} bytes")]
+    ResponseTooLarge,
+    #[error("invalid response head: {0}")]
+    InvalidResponse(httparse::Error),
+    #[error("handshake failed: {0}")]
+    Handshake(#[from] HandshakeError),
+}
+
+impl From<HeadError> for ConnectError {
+    fn from(err: HeadError) -> Self {
+        match err {
+            HeadError::Io(err) => ConnectError::Io(err),
+            HeadError::TooLarge => ConnectError::ResponseTooLarge,
+            HeadError::Invalid(err) => ConnectError::InvalidResponse(err),
+        }
+    }
+}
+
+// Performs the client handshake over `transport` and returns the connection along with the
+// server response. `request` is usually built with `upgrade_request`. The extensions in `config`
+// are offered, unless the request has a `Sec-WebSocket-Extensions` header already, and replaced by
+// those accepted by the server.
+pub async fn connect<T: AsyncRead + AsyncWrite + Unpin>(
+    mut transport: T,
+    mut request: Request<()>,
+    mut config: WsConfig,
+) -> Result<(WsConnection<T>, Response<()>), ConnectError> {
+    let headers = request.headers_mut();
+    if !config.extensions.is_empty() && !headers.contains_key("Sec-WebSocket-Extensions") {
+        headers.insert(
+            "Sec-WebSocket-Extensions",
+            extension_offer(&config.extensions),
+        );
+    }
+    is_upgrade_request(&request)?;
+    write_request(&mut transport, &request).await?;
+    let (mut buffer, len) = read_head(&mut transport, MAX_RESPONSE_HEAD_LEN).await?;
+    let response = parse_response(&buffer[..len])?;
+    check_upgrade_response(&request, &response)?;
+    let offered = std::mem::take(&mut config.extensions);
+    config.extensions = response_extensions(&response, offered).ok_or_else(|| {
+        let accepted = response.headers().get_all("Sec-WebSocket-Extensions");
+        HandshakeError::Extensions(accepted.iter().cloned().collect())
+    })?;
+    buffer.drain(..len);
+    let handshake = WsHandshakeInfo::new(&request, &response);
+    let connection =
+        WsConnection::with_buffered(transport, config, buffer).with_handshake(handshake);
+    Ok((connection, response))
+}
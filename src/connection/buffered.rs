@@ -0,0 +1,59 @@
+use futures::prelude::*;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// A transport that yields bytes read before the connection was set up, e.g. following the
+// handshake, before reading from the underlying transport again.
+pub(crate) struct Buffered<T> {
+    transport: T,
+    buffer: Vec<u8>,
+    pos: usize,
+}
+
+impl<T> Buffered<T> {
+    pub(crate) fn new(transport: T, buffer: Vec<u8>) -> Self {
+        Self {
+            transport,
+            buffer,
+            pos: 0,
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Buffered<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.pos == this.buffer.len() {
+            return Pin::new(&mut this.transport).poll_read(cx, buf);
+        }
+        let n = buf.len().min(this.buffer.len() - this.pos);
+        buf[..n].copy_from_slice(&this.buffer[this.pos..this.pos + n]);
+        this.pos += n;
+        if this.pos == this.buffer.len() {
+            this.buffer = Vec::new();
+            this.pos = 0;
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Buffered<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.transport).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.transport).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.transport).poll_close(cx)
+    }
+}
@@ -0,0 +1,179 @@
+use futures::Future;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+pub type WsSleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// Source of time for keepalive, pong and idle timeouts and round trip time measurements.
+pub trait WsClock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> WsSleep;
+}
+
+// A timeout that can be pushed back without allocating a new sleep every time. The sleep is only
+// replaced when it fires before the current deadline, or when the deadline was moved forward.
+pub(crate) struct Deadline {
+    sleep: Option<WsSleep>,
+    armed: Instant,
+    deadline: Instant,
+}
+
+impl Deadline {
+    pub(crate) fn new(clock: &dyn WsClock, duration: Duration) -> Self {
+        let deadline = clock.now() + duration;
+        Self {
+            sleep: Some(clock.sleep(duration)),
+            armed: deadline,
+            deadline,
+        }
+    }
+    pub(crate) fn reset(&mut self, clock: &dyn WsClock, duration: Duration) {
+        self.deadline = clock.now() + duration;
+        if self.deadline < self.armed {
+            self.sleep = None;
+        }
+    }
+    // Resolves once the deadline has passed, and keeps doing so until it is reset.
+    pub(crate) fn poll(&mut self, clock: &dyn WsClock, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if let Some(sleep) = &mut self.sleep {
+                futures::ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+            let now = clock.now();
+            if now >= self.deadline {
+                return Poll::Ready(());
+            }
+            self.sleep = Some(clock.sleep(self.deadline - now));
+            self.armed = self.deadline;
+        }
+    }
+}
+
+// The clock used by `WsConfig::client` and `WsConfig::server`. Without the `async-io` or `tokio`
+// feature this is a `VirtualClock`, so timeouts only fire if a clock is configured explicitly.
+pub(crate) fn default_clock() -> Arc<dyn WsClock> {
+    #[cfg(feature = "async-io")]
+    {
+        Arc::new(AsyncIoClock)
+    }
+    #[cfg(all(feature = "tokio", not(feature = "async-io")))]
+    {
+        Arc::new(TokioClock)
+    }
+    #[cfg(not(any(feature = "async-io", feature = "tokio")))]
+    {
+        Arc::new(VirtualClock::new())
+    }
+}
+
+#[cfg(feature = "async-io")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AsyncIoClock;
+
+#[cfg(feature = "async-io")]
+impl WsClock for AsyncIoClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep(&self, duration: Duration) -> WsSleep {
+        let timer = async_io::Timer::after(duration);
+        Box::pin(async move {
+            timer.await;
+        })
+    }
+}
+
+// Requires a tokio runtime with the time driver enabled.
+#[cfg(feature = "tokio")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TokioClock;
+
+#[cfg(feature = "tokio")]
+impl WsClock for TokioClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+    fn sleep(&self, duration: Duration) -> WsSleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+// A clock that only moves when advanced manually, for deterministic tests of timeout behaviour.
+#[derive(Clone)]
+pub struct VirtualClock {
+    state: Arc<Mutex<VirtualClockState>>,
+}
+
+struct VirtualClockState {
+    now: Instant,
+    wakers: Vec<Waker>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(VirtualClockState {
+                now: Instant::now(),
+                wakers: Vec::new(),
+            })),
+        }
+    }
+    pub fn advance(&self, duration: Duration) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.now += duration;
+            std::mem::take(&mut state.wakers)
+        };
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for VirtualClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtualClock")
+            .field("now", &self.now())
+            .finish()
+    }
+}
+
+impl WsClock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+    fn sleep(&self, duration: Duration) -> WsSleep {
+        Box::pin(VirtualSleep {
+            state: self.state.clone(),
+            deadline: self.now() + duration,
+        })
+    }
+}
+
+struct VirtualSleep {
+    state: Arc<Mutex<VirtualClockState>>,
+    deadline: Instant,
+}
+
+impl Future for VirtualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.now >= self.deadline {
+            return Poll::Ready(());
+        }
+        if !state.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
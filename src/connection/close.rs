@@ -1,13 +1,97 @@
+use crate::connection::waker::{new_waker, Parent, Wakers};
 use crate::connection::WsConnectionError;
 use crate::frame::WsControlFramePayload;
-use std::{error, io};
+use futures::{AsyncRead, AsyncWrite, Future};
+use std::io;
+use std::ops::DerefMut;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WsCloseStatus {
+    pub code: Option<u16>,
+    pub reason: String,
+    pub initiated_by: WsCloseInitiator,
+}
+
+impl WsCloseStatus {
+    pub(crate) fn new(payload: &WsControlFramePayload, initiated_by: WsCloseInitiator) -> Self {
+        let (code, reason) = match payload.close_body() {
+            Ok(Some((code, reason))) => (Some(code), reason.to_string()),
+            Ok(None) => (None, String::new()),
+            Err(_) => unreachable!("malformed close bodies fail the connection when decoded"),
+        };
+        Self {
+            code,
+            reason,
+            initiated_by,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WsCloseInitiator {
+    Local,
+    Remote,
+}
+
+pub struct WsClose<T: AsyncRead + AsyncWrite + Unpin> {
+    // Holds the rejected code if it may not be sent in a close frame.
+    payload: Result<WsControlFramePayload, u16>,
+    parent: Parent<T>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> WsClose<T> {
+    pub(crate) fn new(parent: &Parent<T>, code: u16, reason: &str) -> Self {
+        let payload = match code {
+            1000..=1003 | 1007..=1014 | 3000..=4999 => {
+                Ok(WsControlFramePayload::close(code, reason))
+            }
+            _ => Err(code),
+        };
+        Self {
+            payload,
+            parent: parent.clone(),
+        }
+    }
+    pub fn err(&self) -> Option<Arc<WsConnectionError>> {
+        self.parent.lock().unwrap().0.err()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Future for WsClose<T> {
+    type Output = Result<WsCloseStatus, Arc<WsConnectionError>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let payload = match self.payload {
+            Ok(payload) => payload,
+            Err(code) => {
+                let err = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid close code {}", code),
+                );
+                return Poll::Ready(Err(Arc::new(err.into())));
+            }
+        };
+        let mut guard = self.parent.lock().unwrap();
+        let (inner, wakers) = guard.deref_mut();
+        Wakers::register(&mut wakers.close_wakers, cx.waker());
+        let waker = new_waker(Arc::downgrade(&self.parent));
+        let p = inner.poll_close(&mut Context::from_waker(&waker), payload);
+        if p.is_ready() {
+            wakers.wake();
+        }
+        p
+    }
+}
 
 pub(crate) enum CloseState {
     None,
     Queued(WsControlFramePayload),
-    Sent(WsControlFramePayload),
+    Sent,
     ReceivedQueued(WsControlFramePayload),
-    ReceivedSent(WsControlFramePayload),
+    ReceivedSent,
 }
 
 impl CloseState {
@@ -15,7 +99,7 @@ impl CloseState {
         match self {
             Self::None => *self = Self::ReceivedQueued(payload),
             Self::Queued(payload) => *self = Self::ReceivedQueued(*payload),
-            Self::Sent(payload) => *self = Self::ReceivedSent(*payload),
+            Self::Sent => *self = Self::ReceivedSent,
             _ => panic!("duplicate incoming close frame"),
         }
     }
@@ -25,57 +109,38 @@ impl CloseState {
             _ => panic!("duplicate outgoing close frame"),
         }
     }
-    pub(crate) fn queued(&self) -> bool {
-        match self {
-            Self::Queued(_) | Self::ReceivedQueued(_) => true,
-            _ => false,
-        }
-    }
     pub(crate) fn unqueue(&mut self) -> Option<WsControlFramePayload> {
         match self {
             Self::Queued(payload) => {
                 let payload = *payload;
-                *self = Self::Sent(payload);
+                *self = Self::Sent;
                 Some(payload)
             }
             Self::ReceivedQueued(payload) => {
                 let payload = *payload;
-                *self = Self::ReceivedSent(payload);
+                *self = Self::ReceivedSent;
                 Some(payload)
             }
             _ => None,
         }
     }
-    pub(crate) fn receive_err<E: error::Error>(&mut self, err: E) -> E {
-        match self {
-            Self::None => *self = Self::ReceivedQueued((1002, &err).into()),
-            CloseState::Queued(payload) => *self = Self::ReceivedQueued(*payload),
-            CloseState::Sent(payload) => *self = Self::ReceivedSent(*payload),
-            CloseState::ReceivedQueued(_) => {}
-            CloseState::ReceivedSent(_) => {}
-        }
-        return err;
-    }
-    pub(crate) fn write_err(&mut self, err: io::Error) -> Option<WsConnectionError> {
+    pub(crate) fn initiator(&self) -> Option<WsCloseInitiator> {
         match self {
-            Self::None => *self = Self::ReceivedSent((1002, &err).into()),
-            CloseState::Queued(payload) => *self = Self::ReceivedSent(*payload),
-            CloseState::Sent(payload) => *self = Self::ReceivedSent(*payload),
-            CloseState::ReceivedQueued(payload) => *self = Self::ReceivedSent(*payload),
-            CloseState::ReceivedSent(_) => {}
+            Self::None => None,
+            Self::Queued(_) | Self::Sent => Some(WsCloseInitiator::Local),
+            Self::ReceivedQueued(_) | Self::ReceivedSent => Some(WsCloseInitiator::Remote),
         }
-        return Some(WsConnectionError::Io(err));
     }
     pub(crate) fn open_for_sending(&self) -> bool {
         match self {
             Self::None | Self::ReceivedQueued(_) | Self::Queued(_) => true,
-            Self::Sent(_) | Self::ReceivedSent(_) => false,
+            Self::Sent | Self::ReceivedSent => false,
         }
     }
     pub(crate) fn open_for_receiving(&self) -> bool {
         match self {
-            CloseState::None | CloseState::Queued(_) | CloseState::Sent(_) => true,
-            CloseState::ReceivedQueued(_) | CloseState::ReceivedSent(_) => false,
+            CloseState::None | CloseState::Queued(_) | CloseState::Sent => true,
+            CloseState::ReceivedQueued(_) | CloseState::ReceivedSent => false,
         }
     }
 }
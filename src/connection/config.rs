@@ -1,24 +1,63 @@
+use crate::connection::clock::{default_clock, WsClock};
+use crate::extension::WsExtension;
+use std::sync::Arc;
 use std::time::Duration;
 
+#[non_exhaustive]
 pub struct WsConfig {
     pub mask: bool,
-    pub timeout: Duration,
-    _private: (),
+    // A ping is sent after receiving nothing for `ping_interval`. The connection fails if nothing
+    // is received within `pong_timeout` after that.
+    pub ping_interval: Option<Duration>,
+    pub pong_timeout: Duration,
+    // Closes the connection with code 1001 once no message was sent or received for this long.
+    pub idle_timeout: Option<Duration>,
+    // Time allowed for the close handshake once a close frame was sent. When it passes, the
+    // connection fails with a timeout and the transport is shut down.
+    pub close_timeout: Duration,
+    pub clock: Arc<dyn WsClock>,
+    // Limits for incoming messages. Exceeding them fails the connection with close code 1009.
+    pub max_message_size: usize,
+    pub max_frame_payload_len: u64,
+    pub max_fragments: usize,
+    // Maximum size of outgoing data frames including the frame head. Smaller values than 7 bytes
+    // for masked and 3 bytes for unmasked frames are raised to that minimum.
+    pub max_outgoing_frame_size: u64,
+    // Negotiated extensions in the order they apply to outgoing messages, see
+    // `http::negotiate_extensions` and `http::response_extensions`. `max_message_size` also limits
+    // the size of incoming messages after they were transformed.
+    pub extensions: Vec<Box<dyn WsExtension>>,
 }
 
 impl WsConfig {
     pub fn client() -> Self {
         Self {
             mask: true,
-            timeout: Duration::from_secs(10),
-            _private: (),
+            ping_interval: Some(Duration::from_secs(10)),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: None,
+            close_timeout: Duration::from_secs(10),
+            clock: default_clock(),
+            max_message_size: 64 * 1024 * 1024,
+            max_frame_payload_len: 64 * 1024 * 1024,
+            max_fragments: 65536,
+            max_outgoing_frame_size: 1300,
+            extensions: Vec::new(),
         }
     }
     pub fn server() -> Self {
         Self {
             mask: false,
-            timeout: Duration::from_secs(10),
-            _private: (),
+            ping_interval: Some(Duration::from_secs(10)),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: None,
+            close_timeout: Duration::from_secs(10),
+            clock: default_clock(),
+            max_message_size: 64 * 1024 * 1024,
+            max_frame_payload_len: 64 * 1024 * 1024,
+            max_fragments: 65536,
+            max_outgoing_frame_size: 1300,
+            extensions: Vec::new(),
         }
     }
 }
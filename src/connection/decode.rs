@@ -1,6 +1,8 @@
-use crate::connection::WsConnectionError;
+use crate::connection::{WsConfig, WsConnectionError};
+use crate::extension::ExtensionDecoder;
 use crate::frame::{
-    FrameDecoderState, FramePayloadReaderState, WsControlFrame, WsControlFrameKind, WsFrame,
+    FrameDecoderState, FramePayloadReaderState, WsControlFrame, WsControlFrameKind,
+    WsControlFramePayload, WsDataFrame, WsFrame,
 };
 use crate::message::WsMessageKind;
 use futures::task::{Context, Poll};
@@ -18,25 +20,65 @@ pub(crate) enum DecodeState {
         first_frame_mask: [u8; 4],
         first_frame_payload_len: u64,
         fin: bool,
+        rsv: u8,
     },
     WaitingForMessageContinuation {
         frame_decoder: FrameDecoderState,
-        utf8: Option<Incomplete>,
+        message: MessageProgress,
     },
     ReadingDataFramePayload {
         payload: FramePayloadReaderState,
         fin: bool,
-        utf8: Option<Incomplete>,
+        message: MessageProgress,
     },
     MessageEnd,
     Control {
         frame: WsControlFrame,
-        continue_message: Option<Option<Incomplete>>,
+        continue_message: Option<MessageProgress>,
     },
     Err(WsConnectionError),
     Done,
 }
 
+// State of the message being received, carried along its frames.
+#[derive(Copy, Clone)]
+pub(crate) struct MessageProgress {
+    utf8: Option<Incomplete>,
+    payload_len: u64,
+    frames: usize,
+    // RSV bits of the first frame, selecting the extensions which transform the message.
+    rsv: u8,
+    transformed_len: u64,
+}
+
+impl MessageProgress {
+    fn new(kind: WsMessageKind, rsv: u8) -> Self {
+        Self {
+            utf8: match kind {
+                WsMessageKind::Binary => None,
+                WsMessageKind::Text => Some(Incomplete::empty()),
+            },
+            payload_len: 0,
+            frames: 0,
+            rsv,
+            transformed_len: 0,
+        }
+    }
+    fn check_frame(&self, frame: &WsDataFrame, config: &WsConfig) -> Result<(), WsConnectionError> {
+        if frame.payload_len > config.max_frame_payload_len
+            || self.payload_len + frame.payload_len > config.max_message_size as u64
+            || self.frames >= config.max_fragments
+        {
+            return Err(WsConnectionError::MessageTooBig);
+        }
+        Ok(())
+    }
+    fn add_frame(&mut self, frame: &WsDataFrame) {
+        self.payload_len += frame.payload_len;
+        self.frames += 1;
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub(crate) enum DecodeReady {
     Control(WsControlFrameKind),
@@ -57,6 +99,8 @@ impl DecodeState {
         &mut self,
         transport: &mut T,
         cx: &mut Context<'_>,
+        config: &WsConfig,
+        extensions: Option<&ExtensionDecoder>,
     ) -> Poll<DecodeReady> {
         match self {
             DecodeState::WaitingForMessageStart { frame_decoder } => {
@@ -70,11 +114,22 @@ impl DecodeState {
                     }
                     Poll::Ready(Ok(WsFrame::Data(frame))) => match frame.kind.message_kind() {
                         Some(kind) => {
+                            let allowed_rsv = extensions.map_or(0, ExtensionDecoder::rsv);
+                            if frame.rsv & !allowed_rsv != 0 {
+                                self.set_err(WsConnectionError::UnexpectedRsv);
+                                return Poll::Ready(DecodeReady::Error);
+                            }
+                            let message = MessageProgress::new(kind, frame.rsv);
+                            if let Err(err) = message.check_frame(&frame, config) {
+                                self.set_err(err);
+                                return Poll::Ready(DecodeReady::Error);
+                            }
                             *self = Self::MessageStart {
                                 kind,
                                 first_frame_mask: frame.mask,
                                 fin: frame.fin,
                                 first_frame_payload_len: frame.payload_len,
+                                rsv: frame.rsv,
                             };
                             Poll::Ready(DecodeReady::MessageStart)
                         }
@@ -92,21 +147,32 @@ impl DecodeState {
             }
             DecodeState::WaitingForMessageContinuation {
                 frame_decoder,
-                utf8,
+                message,
             } => match frame_decoder.poll(transport, cx) {
                 Poll::Ready(Ok(WsFrame::Control(frame))) => {
                     *self = Self::Control {
                         frame,
-                        continue_message: Some(*utf8),
+                        continue_message: Some(*message),
                     };
                     Poll::Ready(DecodeReady::Control(frame.kind()))
                 }
                 Poll::Ready(Ok(WsFrame::Data(frame))) => match frame.kind.message_kind() {
                     None => {
+                        // Extensions only use the RSV bits of the first frame of a message.
+                        if frame.rsv != 0 {
+                            self.set_err(WsConnectionError::UnexpectedRsv);
+                            return Poll::Ready(DecodeReady::Error);
+                        }
+                        if let Err(err) = message.check_frame(&frame, config) {
+                            self.set_err(err);
+                            return Poll::Ready(DecodeReady::Error);
+                        }
+                        let mut message = *message;
+                        message.add_frame(&frame);
                         *self = Self::ReadingDataFramePayload {
                             payload: frame.payload_reader(),
                             fin: frame.fin,
-                            utf8: *utf8,
+                            message,
                         };
                         Poll::Ready(DecodeReady::MessageData)
                     }
@@ -126,7 +192,7 @@ impl DecodeState {
             DecodeState::Done => Poll::Ready(DecodeReady::Done),
             DecodeState::Control { frame, .. } => Poll::Ready(DecodeReady::Control(frame.kind())),
             DecodeState::MessageStart { .. } => Poll::Ready(DecodeReady::MessageStart),
-            DecodeState::MessageEnd { .. } => Poll::Ready(DecodeReady::MessageEnd),
+            DecodeState::MessageEnd => Poll::Ready(DecodeReady::MessageEnd),
         }
     }
     pub(crate) fn poll_read<T: AsyncRead + AsyncWrite + Unpin>(
@@ -134,9 +200,19 @@ impl DecodeState {
         transport: &mut T,
         cx: &mut Context<'_>,
         buf: &mut [u8],
+        extensions: Option<&mut ExtensionDecoder>,
+        config: &WsConfig,
     ) -> Poll<usize> {
         match self {
-            DecodeState::ReadingDataFramePayload { payload, fin, utf8 } => {
+            DecodeState::ReadingDataFramePayload { message, .. } if message.rsv != 0 => {
+                let extensions = extensions.expect("transformed message without extensions");
+                self.poll_read_transformed(transport, cx, buf, extensions, config)
+            }
+            DecodeState::ReadingDataFramePayload {
+                payload,
+                fin,
+                message,
+            } => {
                 let n = match payload.poll_read(transport, cx, buf) {
                     Poll::Ready(Ok(n)) => n,
                     Poll::Pending => return Poll::Pending,
@@ -146,7 +222,10 @@ impl DecodeState {
                     }
                 };
                 let frame_finished = payload.finished();
-                if let Err(err) = Self::validate_utf8(utf8, &buf[0..n], *fin && frame_finished) {
+                let message_finished = *fin && frame_finished;
+                if let Err(err) =
+                    Self::validate_utf8(&mut message.utf8, &buf[0..n], message_finished)
+                {
                     self.set_err(err);
                     return Poll::Ready(0);
                 }
@@ -155,7 +234,7 @@ impl DecodeState {
                         true => Self::MessageEnd,
                         false => Self::WaitingForMessageContinuation {
                             frame_decoder: FrameDecoderState::new(),
-                            utf8: *utf8,
+                            message: *message,
                         },
                     };
                 }
@@ -164,9 +243,95 @@ impl DecodeState {
             _ => Poll::Ready(0),
         }
     }
+    // Reads the payload of transformed frames into the extensions and returns their output.
+    // Returns 0 when moving on to the next frame or the end of the message.
+    fn poll_read_transformed<T: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        transport: &mut T,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+        extensions: &mut ExtensionDecoder,
+        config: &WsConfig,
+    ) -> Poll<usize> {
+        let (payload, fin, message) = match self {
+            DecodeState::ReadingDataFramePayload {
+                payload,
+                fin,
+                message,
+            } => (payload, *fin, message),
+            _ => unreachable!(),
+        };
+        if !extensions.is_started() {
+            extensions.start_message(message.rsv);
+        }
+        loop {
+            let n = match extensions.read(buf) {
+                Ok(n) => n,
+                Err(err) => {
+                    self.set_err(err);
+                    return Poll::Ready(0);
+                }
+            };
+            if n > 0 {
+                // Limits the transformed size, so small payloads can't expand unboundedly.
+                message.transformed_len += n as u64;
+                let result = match message.transformed_len > config.max_message_size as u64 {
+                    true => Err(WsConnectionError::MessageTooBig),
+                    false => Self::validate_utf8(&mut message.utf8, &buf[0..n], false),
+                };
+                if let Err(err) = result {
+                    self.set_err(err);
+                    return Poll::Ready(0);
+                }
+                return Poll::Ready(n);
+            }
+            if !payload.finished() {
+                let mut input = [0u8; 1300];
+                match payload.poll_read(transport, cx, &mut input) {
+                    Poll::Ready(Ok(n)) => extensions.push(&input[0..n]),
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Err(err)) => {
+                        self.set_err(err.into());
+                        return Poll::Ready(0);
+                    }
+                }
+                continue;
+            }
+            if fin && extensions.end() {
+                continue;
+            }
+            let mut message = *message;
+            if fin {
+                let result = match extensions.finished() {
+                    true => Self::validate_utf8(&mut message.utf8, &[], true),
+                    false => Err(WsConnectionError::InvalidExtensionData),
+                };
+                extensions.end_message();
+                if let Err(err) = result {
+                    self.set_err(err);
+                    return Poll::Ready(0);
+                }
+            }
+            *self = match fin {
+                true => Self::MessageEnd,
+                false => Self::WaitingForMessageContinuation {
+                    frame_decoder: FrameDecoderState::new(),
+                    message,
+                },
+            };
+            return Poll::Ready(0);
+        }
+    }
     pub fn set_err(&mut self, err: WsConnectionError) {
         *self = Self::Err(err)
     }
+    // The close frame to send to the peer when failing the connection due to the current error.
+    pub fn err_close_payload(&self) -> Option<WsControlFramePayload> {
+        match self {
+            Self::Err(err) => err.close_code().map(|code| (code, err).into()),
+            _ => None,
+        }
+    }
     pub fn take_err(&mut self) -> Option<WsConnectionError> {
         if let Self::Err(_err) = self {
             if let Self::Err(err) = replace(self, Self::Done) {
@@ -199,16 +364,17 @@ impl DecodeState {
             first_frame_mask,
             first_frame_payload_len,
             fin,
+            rsv,
         } = self
         {
             let kind = *kind;
+            let mut message = MessageProgress::new(kind, *rsv);
+            message.payload_len = *first_frame_payload_len;
+            message.frames = 1;
             *self = Self::ReadingDataFramePayload {
                 payload: FramePayloadReaderState::new(*first_frame_mask, *first_frame_payload_len),
                 fin: *fin,
-                utf8: match kind {
-                    WsMessageKind::Binary => None,
-                    WsMessageKind::Text => Some(Incomplete::empty()),
-                },
+                message,
             };
             return Some(kind);
         }
@@ -232,9 +398,9 @@ impl DecodeState {
                 let frame = *frame;
                 *self = match (frame.kind(), continue_message) {
                     (WsControlFrameKind::Close, _) => Self::Done,
-                    (_, Some(utf8)) => Self::WaitingForMessageContinuation {
+                    (_, Some(message)) => Self::WaitingForMessageContinuation {
                         frame_decoder: FrameDecoderState::new(),
-                        utf8: *utf8,
+                        message: *message,
                     },
                     (_, None) => Self::WaitingForMessageStart {
                         frame_decoder: FrameDecoderState::new(),
@@ -1,23 +1,32 @@
 use crate::connection::encode::EncodeState::Sending;
-use crate::connection::WsConnectionError;
+use crate::connection::{WsConfig, WsConnectionError};
+use crate::extension::{ExtensionEncoder, RSV1, RSV2, RSV3};
 use crate::frame::{
-    payload_mask, FrameHead, WsControlFrame, WsControlFrameKind, WsDataFrameKind, WsFrameKind,
+    max_payload_len, payload_mask, FrameHead, WsControlFrame, WsControlFrameKind, WsDataFrameKind,
+    WsFrameKind,
 };
 use crate::message::WsMessageKind;
 use futures::task::{Context, Poll};
 use futures::{io, AsyncRead, AsyncWrite};
 use rand::{thread_rng, RngCore};
-use std::mem::replace;
+use std::collections::VecDeque;
+use std::mem::{replace, take};
 use std::pin::Pin;
 
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum EncodeState {
     Sending {
         frame_in_progress: Option<FrameInProgress>,
         next_data_frame_kind: Option<WsDataFrameKind>,
-        queued_control: Option<WsControlFrame>,
+        queued_control: VecDeque<WsControlFrame>,
+        sized_payload: Option<SizedPayload>,
+        extensions: Option<ExtensionEncoder>,
         flushed: Option<bool>,
         closing: bool,
+        // Ping frames written to the transport, before and after they were flushed.
+        unflushed_pings: usize,
+        flushed_pings: usize,
     },
     Err(io::Error),
     Done,
@@ -33,13 +42,17 @@ pub(crate) enum EncodeReady {
 }
 
 impl EncodeState {
-    pub fn new() -> EncodeState {
+    pub fn new(extensions: Option<ExtensionEncoder>) -> EncodeState {
         EncodeState::Sending {
             frame_in_progress: None,
             next_data_frame_kind: None,
-            queued_control: None,
+            queued_control: VecDeque::new(),
+            sized_payload: None,
+            extensions,
             flushed: Some(false),
             closing: false,
+            unflushed_pings: 0,
+            flushed_pings: 0,
         }
     }
     pub fn start_message(&mut self, kind: WsMessageKind) {
@@ -57,7 +70,76 @@ impl EncodeState {
             unreachable!()
         }
     }
-    pub fn end_message(&mut self, mask: bool) {
+    // Starts a message consisting of a single frame with a known payload length. The payload is
+    // written straight to the transport by `poll_write_sized`.
+    pub fn start_sized_message(&mut self, kind: WsMessageKind, len: u64, config: &WsConfig) {
+        if let Sending {
+            next_data_frame_kind: None,
+            frame_in_progress: frame_in_progress @ None,
+            sized_payload,
+            flushed,
+            ..
+        } = self
+        {
+            flushed.take();
+            let frame = FrameInProgress::new_sized(kind.frame_kind(), config.mask, len);
+            // Masked payload is copied into a scratch buffer of at most one outgoing frame.
+            let scratch_len = match config.mask {
+                true => len.min(max_payload_len(true, config.max_outgoing_frame_size)),
+                false => 0,
+            };
+            *sized_payload = Some(SizedPayload {
+                mask: frame.mask,
+                written: 0,
+                len,
+                scratch: vec![0u8; scratch_len as usize],
+            });
+            *frame_in_progress = Some(frame);
+        } else {
+            unreachable!()
+        }
+    }
+    pub fn is_sized(&self) -> bool {
+        matches!(
+            self,
+            Sending {
+                sized_payload: Some(_),
+                ..
+            }
+        )
+    }
+    pub fn end_message(&mut self, config: &WsConfig) {
+        if let Sending {
+            sized_payload: sized_payload @ Some(_),
+            ..
+        } = self
+        {
+            let sized = sized_payload.take().unwrap();
+            if sized.written != sized.len {
+                // The frame head announced more payload, so the frame can't be completed.
+                *self = Self::Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "sized message ended before its announced length",
+                ));
+            } else {
+                self.start_flushing()
+            }
+            return;
+        }
+        if let Sending {
+            next_data_frame_kind: Some(_),
+            extensions: Some(extensions),
+            ..
+        } = self
+        {
+            // The final frame is written by `poll` once all transformed output was drained.
+            if !extensions.is_ending() {
+                if let Err(err) = extensions.finish() {
+                    *self = Self::Err(err);
+                }
+            }
+            return;
+        }
         if let Sending {
             next_data_frame_kind,
             frame_in_progress,
@@ -68,7 +150,7 @@ impl EncodeState {
                 if let Some(frame) = frame_in_progress {
                     frame.start_writing(true);
                 } else {
-                    let mut frame = FrameInProgress::new(kind.frame_kind(), mask);
+                    let mut frame = FrameInProgress::new_data(kind, config);
                     frame.start_writing(true);
                     *frame_in_progress = Some(frame);
                 }
@@ -77,41 +159,134 @@ impl EncodeState {
         }
     }
     pub fn queue_control(&mut self, control: WsControlFrame) {
-        if let Sending { queued_control, .. } = self {
-            if let Some(queued) = queued_control {
-                if queued.kind() == WsControlFrameKind::Close {
-                    return;
-                }
+        if let Sending {
+            queued_control,
+            closing,
+            ..
+        } = self
+        {
+            // Nothing may follow a close frame and only the most recent pong has to be sent.
+            if *closing
+                || queued_control
+                    .iter()
+                    .any(|queued| queued.kind() == WsControlFrameKind::Close)
+            {
+                return;
             }
-            *queued_control = Some(control);
+            if control.kind() == WsControlFrameKind::Pong {
+                queued_control.retain(|queued| queued.kind() != WsControlFrameKind::Pong);
+            }
+            queued_control.push_back(control);
         }
     }
-    pub fn append_data(&mut self, buf: &[u8], mask: bool) -> usize {
+    pub fn append_data(&mut self, buf: &[u8], config: &WsConfig) -> usize {
         if let Sending {
             next_data_frame_kind,
             frame_in_progress,
+            extensions,
             flushed,
             ..
         } = self
         {
+            if let Some(extensions) = extensions {
+                assert!(next_data_frame_kind.is_some());
+                flushed.take();
+                // Limit the input per call, so that the transformed output is subject to
+                // backpressure from the transport.
+                let max_payload_len = max_payload_len(config.mask, config.max_outgoing_frame_size);
+                let n = buf.len().min(max_payload_len as usize);
+                if let Err(err) = extensions.write(&buf[..n]) {
+                    *self = Self::Err(err);
+                    return 0;
+                }
+                drain_extensions(extensions, frame_in_progress, next_data_frame_kind, config);
+                return n;
+            }
             let kind = next_data_frame_kind
                 .replace(WsDataFrameKind::Continuation)
                 .unwrap();
             flushed.take();
             frame_in_progress
-                .get_or_insert_with(|| FrameInProgress::new(kind.frame_kind(), mask))
+                .get_or_insert_with(|| FrameInProgress::new_data(kind, config))
                 .append_data(buf)
         } else {
             unreachable!()
         }
     }
+    pub fn poll_write_sized<T: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        transport: &mut T,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if let Sending {
+            sized_payload: Some(sized),
+            frame_in_progress: None,
+            flushed,
+            ..
+        } = self
+        {
+            let remaining = sized.len - sized.written;
+            if remaining < buf.len() as u64 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "write exceeds the announced length of a sized message",
+                )));
+            }
+            let buf = match sized.mask {
+                [0, 0, 0, 0] => buf,
+                mask => {
+                    let n = buf.len().min(sized.scratch.len());
+                    let masked = &mut sized.scratch[..n];
+                    masked.copy_from_slice(&buf[..n]);
+                    payload_mask(mask, sized.written as usize, masked);
+                    masked
+                }
+            };
+            match Pin::new(&mut *transport).poll_write(cx, buf) {
+                Poll::Ready(Ok(n)) => {
+                    flushed.take();
+                    sized.written += n as u64;
+                    Poll::Ready(Ok(n))
+                }
+                Poll::Ready(Err(err)) => {
+                    *self = Self::Err(err);
+                    Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+                }
+                Poll::Pending => Poll::Pending,
+            }
+        } else {
+            unreachable!()
+        }
+    }
     pub fn start_flushing(&mut self) {
-        if let Sending { flushed, .. } = self {
+        if let Sending {
+            flushed,
+            extensions,
+            next_data_frame_kind,
+            ..
+        } = self
+        {
+            if let Some(extensions) = extensions {
+                if next_data_frame_kind.is_some() && flushed.is_none() && !extensions.is_ending() {
+                    if let Err(err) = extensions.flush() {
+                        *self = Self::Err(err);
+                        return;
+                    }
+                }
+            }
             flushed.get_or_insert(false);
         } else {
             unreachable!()
         }
     }
+    // The number of ping frames flushed since the last call.
+    pub fn take_flushed_pings(&mut self) -> usize {
+        match self {
+            Sending { flushed_pings, .. } => take(flushed_pings),
+            _ => 0,
+        }
+    }
     pub fn take_err(&mut self) -> Option<WsConnectionError> {
         if let EncodeState::Err(_) = self {
             let old = replace(self, EncodeState::Done);
@@ -126,7 +301,7 @@ impl EncodeState {
         &mut self,
         transport: &mut T,
         cx: &mut Context<'_>,
-        mask: bool,
+        config: &WsConfig,
     ) -> Poll<EncodeReady> {
         loop {
             match self {
@@ -134,24 +309,78 @@ impl EncodeState {
                     frame_in_progress,
                     next_data_frame_kind,
                     queued_control,
+                    sized_payload,
+                    extensions,
                     flushed,
                     closing,
+                    unflushed_pings,
+                    flushed_pings,
                 } => {
+                    if let Some(extensions) = extensions
+                        .as_mut()
+                        .filter(|_| next_data_frame_kind.is_some())
+                    {
+                        drain_extensions(
+                            extensions,
+                            frame_in_progress,
+                            next_data_frame_kind,
+                            config,
+                        );
+                        let writing = frame_in_progress.as_ref().map(|f| f.written.is_some());
+                        if extensions.is_ending()
+                            && extensions.available().is_empty()
+                            && writing != Some(true)
+                        {
+                            let kind = next_data_frame_kind.take().unwrap();
+                            let rsv = extensions.rsv();
+                            frame_in_progress
+                                .get_or_insert_with(|| {
+                                    FrameInProgress::new_transformed(kind, rsv, config)
+                                })
+                                .start_writing(true);
+                            extensions.end();
+                            flushed.get_or_insert(false);
+                        }
+                    }
                     if let Some(frame) = frame_in_progress {
                         match frame.poll(transport, cx) {
                             Poll::Ready(FrameInProgressReady::Buffering) => {
-                                if flushed.is_none() && queued_control.is_none() {
+                                if flushed.is_none() && queued_control.is_empty() {
                                     return Poll::Ready(EncodeReady::Buffering);
                                 }
                                 frame.start_writing(false);
                             }
-                            Poll::Ready(FrameInProgressReady::Written) => *frame_in_progress = None,
+                            Poll::Ready(FrameInProgressReady::Written) => {
+                                if let WsFrameKind::Control(kind) = frame.kind {
+                                    // Control frames are flushed even if everything else was.
+                                    *flushed = Some(false);
+                                    if kind == WsControlFrameKind::Ping {
+                                        *unflushed_pings += 1;
+                                    }
+                                }
+                                *frame_in_progress = None
+                            }
                             Poll::Ready(FrameInProgressReady::Err(err)) => *self = Self::Err(err),
                             Poll::Pending => return Poll::Pending,
                         }
-                    } else if let Some(control) = queued_control.take() {
+                    } else if sized_payload.is_some() {
+                        // Control frames have to wait until the sized frame is complete.
+                        match flushed {
+                            None => return Poll::Ready(EncodeReady::Buffering),
+                            Some(true) => return Poll::Ready(EncodeReady::FlushedFrames),
+                            Some(false) => match Pin::new(&mut *transport).poll_flush(cx) {
+                                Poll::Ready(Ok(())) => {
+                                    *flushed = Some(true);
+                                    *flushed_pings += take(unflushed_pings);
+                                }
+                                Poll::Ready(Err(err)) => *self = Self::Err(err),
+                                Poll::Pending => return Poll::Pending,
+                            },
+                        }
+                    } else if let Some(control) = queued_control.pop_front() {
                         *closing |= control.kind() == WsControlFrameKind::Close;
-                        *frame_in_progress = Some(FrameInProgress::new_control(control, mask));
+                        *frame_in_progress =
+                            Some(FrameInProgress::new_control(control, config.mask));
                         self.start_flushing();
                     } else {
                         match (*flushed, closing) {
@@ -166,7 +395,10 @@ impl EncodeState {
                                 None => return Poll::Ready(EncodeReady::FlushedMessages),
                             },
                             (Some(false), _) => match Pin::new(&mut *transport).poll_flush(cx) {
-                                Poll::Ready(Ok(())) => *flushed = Some(true),
+                                Poll::Ready(Ok(())) => {
+                                    *flushed = Some(true);
+                                    *flushed_pings += take(unflushed_pings);
+                                }
                                 Poll::Ready(Err(err)) => *self = Self::Err(err),
                                 Poll::Pending => return Poll::Pending,
                             },
@@ -180,13 +412,48 @@ impl EncodeState {
     }
 }
 
+// Moves transformed output into data frames, until it is used up or a frame is being written.
+fn drain_extensions(
+    extensions: &mut ExtensionEncoder,
+    frame_in_progress: &mut Option<FrameInProgress>,
+    next_data_frame_kind: &mut Option<WsDataFrameKind>,
+    config: &WsConfig,
+) {
+    while !extensions.available().is_empty() {
+        match frame_in_progress {
+            Some(frame) if frame.written.is_some() => return,
+            Some(frame) => {
+                let n = frame.append_data(extensions.available());
+                extensions.consume(n);
+            }
+            None => {
+                let kind = next_data_frame_kind
+                    .replace(WsDataFrameKind::Continuation)
+                    .unwrap();
+                let rsv = extensions.rsv();
+                *frame_in_progress = Some(FrameInProgress::new_transformed(kind, rsv, config));
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct FrameInProgress {
     kind: WsFrameKind,
-    buffer: [u8; 1300],
+    rsv: u8,
+    buffer: Vec<u8>,
     mask: [u8; 4],
+    head_len: usize,
+    max_payload_len: usize,
     written: Option<usize>,
-    filled: usize,
+}
+
+#[derive(Debug)]
+pub struct SizedPayload {
+    mask: [u8; 4],
+    written: u64,
+    len: u64,
+    scratch: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -196,40 +463,81 @@ enum FrameInProgressReady {
     Err(io::Error),
 }
 
-const FRAME_BUFFER_PAYLOAD_OFFSET: usize = 8;
-
 impl FrameInProgress {
-    fn new(kind: WsFrameKind, mask: bool) -> Self {
+    fn new(kind: WsFrameKind, mask: bool, max_payload_len: usize) -> Self {
+        let mask = match mask {
+            true => loop {
+                let r = thread_rng().next_u32();
+                if r != 0 {
+                    break r.to_ne_bytes();
+                }
+            },
+            false => [0u8, 0u8, 0u8, 0u8],
+        };
+        // Reserve enough space in front of the payload for the largest possible frame head.
+        let head_len = FrameHead {
+            fin: false,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode: kind.opcode(),
+            mask,
+            payload_len: max_payload_len as u64,
+        }
+        .len_bytes();
         FrameInProgress {
             kind,
-            buffer: [0u8; 1300],
-            mask: match mask {
-                true => loop {
-                    let r = thread_rng().next_u32();
-                    if r != 0 {
-                        break r.to_ne_bytes();
-                    }
-                },
-                false => [0u8, 0u8, 0u8, 0u8],
-            },
+            rsv: 0,
+            buffer: vec![0u8; head_len],
+            mask,
+            head_len,
+            max_payload_len,
             written: None,
-            filled: FRAME_BUFFER_PAYLOAD_OFFSET,
         }
     }
+    fn new_data(kind: WsDataFrameKind, config: &WsConfig) -> Self {
+        let max_payload_len = max_payload_len(config.mask, config.max_outgoing_frame_size);
+        Self::new(kind.frame_kind(), config.mask, max_payload_len as usize)
+    }
+    // A frame of a message transformed by extensions, the first of which has their RSV bits set.
+    fn new_transformed(kind: WsDataFrameKind, rsv: u8, config: &WsConfig) -> Self {
+        let mut frame = Self::new_data(kind, config);
+        if kind != WsDataFrameKind::Continuation {
+            frame.rsv = rsv;
+        }
+        frame
+    }
     fn new_control(control: WsControlFrame, mask: bool) -> Self {
-        let mut frame = Self::new(control.kind().frame_kind(), mask);
+        let kind = control.kind().frame_kind();
+        let mut frame = Self::new(kind, mask, kind.max_payload_len() as usize);
         frame.append_data(control.payload());
         frame.start_writing(true);
         frame
     }
+    fn new_sized(kind: WsDataFrameKind, mask: bool, payload_len: u64) -> Self {
+        let mut frame = Self::new(kind.frame_kind(), mask, 0);
+        let head = FrameHead {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode: frame.kind.opcode(),
+            mask: frame.mask,
+            payload_len,
+        };
+        frame.head_len = head.len_bytes();
+        frame.buffer = vec![0u8; frame.head_len];
+        head.encode(&mut frame.buffer);
+        frame.written = Some(0);
+        frame
+    }
     fn append_data(&mut self, buf: &[u8]) -> usize {
         assert!(self.written.is_none());
-        let payload_len = self.buffer.len() - self.filled;
-        let append = buf.len().min(payload_len);
-        let target_slice = &mut self.buffer[self.filled..self.filled + append];
-        target_slice.copy_from_slice(&buf[..append]);
-        payload_mask(self.mask, payload_len, target_slice);
-        self.filled += append;
+        let payload_len = self.buffer.len() - self.head_len;
+        let append = buf.len().min(self.max_payload_len - payload_len);
+        let start = self.buffer.len();
+        self.buffer.extend_from_slice(&buf[..append]);
+        payload_mask(self.mask, payload_len, &mut self.buffer[start..]);
         if append < buf.len() {
             self.start_writing(false)
         }
@@ -239,12 +547,15 @@ impl FrameInProgress {
         assert!(self.written.is_none());
         let head = FrameHead {
             fin,
+            rsv1: self.rsv & RSV1 != 0,
+            rsv2: self.rsv & RSV2 != 0,
+            rsv3: self.rsv & RSV3 != 0,
             opcode: self.kind.opcode(),
             mask: self.mask,
-            payload_len: (self.filled - FRAME_BUFFER_PAYLOAD_OFFSET) as u64,
+            payload_len: (self.buffer.len() - self.head_len) as u64,
         };
-        let offset = FRAME_BUFFER_PAYLOAD_OFFSET - head.len_bytes();
-        head.encode(&mut self.buffer[offset..FRAME_BUFFER_PAYLOAD_OFFSET]);
+        let offset = self.head_len - head.len_bytes();
+        head.encode(&mut self.buffer[offset..self.head_len]);
         self.written = Some(offset);
     }
     fn poll<T: AsyncRead + AsyncWrite + Unpin>(
@@ -257,11 +568,11 @@ impl FrameInProgress {
             Some(offset) => offset,
         };
         loop {
-            match Pin::new(&mut *transport).poll_write(cx, &self.buffer[offset..self.filled]) {
+            match Pin::new(&mut *transport).poll_write(cx, &self.buffer[offset..]) {
                 Poll::Ready(Ok(n)) => {
                     offset += n;
                     self.written = Some(offset);
-                    if offset == self.filled {
+                    if offset == self.buffer.len() {
                         return Poll::Ready(FrameInProgressReady::Written);
                     }
                 }
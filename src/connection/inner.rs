@@ -1,16 +1,18 @@
 use crate::connection::encode::EncodeReady;
 
+use crate::connection::close::WsCloseStatus;
 use crate::connection::config::WsConfig;
 use crate::connection::open::{Open, OpenReady};
 use crate::connection::WsConnectionError;
 use crate::connection::WsConnectionInner::ClosedError;
-use crate::frame::WsControlFramePayload;
+use crate::frame::{WsControlFrame, WsControlFrameKind, WsControlFramePayload};
 use crate::message::WsMessageKind;
 use futures::prelude::*;
 use std::io;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 fn broken_pipe<T>() -> Poll<io::Result<T>> {
     Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
@@ -32,15 +34,16 @@ pub(crate) enum InnerTxReady {
     Closed,
 }
 
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum WsConnectionInner<T: AsyncRead + AsyncWrite + Unpin> {
     Open(Open<T>),
     ClosedError(Arc<WsConnectionError>),
-    ClosedOk(WsControlFramePayload),
+    ClosedOk(WsCloseStatus),
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
-    pub(crate) fn with_config(transport: T, config: WsConfig) -> Self {
-        Self::Open(Open::with_config(transport, config))
+    pub(crate) fn with_config(transport: T, config: WsConfig, buffered: Vec<u8>) -> Self {
+        Self::Open(Open::with_config(transport, config, buffered))
     }
     pub fn err(&self) -> Option<Arc<WsConnectionError>> {
         match self {
@@ -48,6 +51,12 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
             _ => None,
         }
     }
+    pub fn close_status(&self) -> Option<WsCloseStatus> {
+        match self {
+            Self::ClosedOk(status) => Some(status.clone()),
+            _ => None,
+        }
+    }
     pub fn poll(
         &mut self,
         cx: &mut Context,
@@ -80,7 +89,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
             Poll::Ready(EncodeReady::FlushedMessages) => Poll::Ready(InnerTxReady::FlushedMessages),
         };
         if p_rx == Poll::Ready(InnerRxReady::Closed) && p_tx == Poll::Ready(InnerTxReady::Closed) {
-            *self = Self::ClosedOk(open.received_close.unwrap());
+            *self = Self::ClosedOk(open.close_status.take().unwrap());
             return None;
         }
         // Remove this when non-lexical lifetimes become stable.
@@ -93,15 +102,25 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
     pub(crate) fn poll_next_writer(
         &mut self,
         kind: WsMessageKind,
+        len: Option<u64>,
         cx: &mut Context,
     ) -> Poll<Option<WsMessageKind>> {
         let (open, _p_rx, p_tx) = match self.poll(cx) {
             None => return Poll::Ready(None),
             Some(x) => x,
         };
+        if !open.close_state.open_for_sending() {
+            return Poll::Ready(None);
+        }
         match p_tx {
             Poll::Ready(InnerTxReady::FlushedMessages) => {
-                open.encode_state.start_message(kind);
+                match len {
+                    Some(len) => open
+                        .encode_state
+                        .start_sized_message(kind, len, &open.config),
+                    None => open.encode_state.start_message(kind),
+                }
+                open.reset_idle();
                 Poll::Ready(Some(kind))
             }
             _ => Poll::Pending,
@@ -127,9 +146,18 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
                     n => return Poll::Ready(Ok(n)),
                 },
                 Poll::Ready(InnerTxReady::Buffering | InnerTxReady::FlushedFrames) => {
-                    total += open
-                        .encode_state
-                        .append_data(&buf[total..], open.config.mask)
+                    if open.encode_state.is_sized() {
+                        let p = open.encode_state.poll_write_sized(
+                            &mut open.transport,
+                            cx,
+                            &buf[total..],
+                        );
+                        if let Poll::Ready(Err(_)) = p {
+                            self.poll(cx);
+                        }
+                        return p;
+                    }
+                    total += open.encode_state.append_data(&buf[total..], &open.config)
                 }
             }
         }
@@ -163,7 +191,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
                 }
                 Poll::Pending => return Poll::Pending,
                 Poll::Ready(InnerTxReady::Buffering | InnerTxReady::FlushedFrames) => {
-                    open.encode_state.end_message(open.config.mask)
+                    open.encode_state.end_message(&open.config)
                 }
                 Poll::Ready(InnerTxReady::Closed) => return broken_pipe(),
             }
@@ -189,7 +217,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
             None => return Poll::Ready(None),
             Some(x) => x,
         };
-        if !open.reader_is_attached {
+        if !open.reader_is_attached && open.close_state.open_for_receiving() {
             if let Poll::Ready(InnerRxReady::MessageStart) = p_rx {
                 let kind = open.decode_state.take_message_start().unwrap();
                 open.reader_is_attached = true;
@@ -198,6 +226,62 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
         }
         Poll::Pending
     }
+    pub(crate) fn poll_close(
+        &mut self,
+        cx: &mut Context<'_>,
+        payload: WsControlFramePayload,
+    ) -> Poll<Result<WsCloseStatus, Arc<WsConnectionError>>> {
+        if let Self::Open(open) = self {
+            open.close(payload);
+        }
+        match self.poll(cx) {
+            Some(_) => Poll::Pending,
+            None => match self {
+                Self::ClosedOk(status) => Poll::Ready(Ok(status.clone())),
+                Self::ClosedError(err) => Poll::Ready(Err(err.clone())),
+                Self::Open(_) => unreachable!(),
+            },
+        }
+    }
+    pub(crate) fn poll_ping(
+        &mut self,
+        cx: &mut Context<'_>,
+        payload: WsControlFramePayload,
+        id: &mut Option<u64>,
+    ) -> Poll<io::Result<Duration>> {
+        if let (Self::Open(open), None) = (&mut *self, *id) {
+            if !open.close_state.open_for_sending() {
+                return broken_pipe();
+            }
+            *id = Some(open.pings.start(payload));
+            open.encode_state.queue_control(WsControlFrame {
+                kind: WsControlFrameKind::Ping,
+                payload,
+            });
+        }
+        let (open, _p_rx, _p_tx) = match self.poll(cx) {
+            None => return broken_pipe(),
+            Some(x) => x,
+        };
+        match id.and_then(|id| open.pings.take_rtt(id)) {
+            Some(rtt) => {
+                id.take();
+                Poll::Ready(Ok(rtt))
+            }
+            None => Poll::Pending,
+        }
+    }
+    pub(crate) fn cancel_ping(&mut self, id: u64) {
+        if let Self::Open(open) = self {
+            open.pings.cancel(id);
+        }
+    }
+    pub(crate) fn latency(&self) -> Option<Duration> {
+        match self {
+            Self::Open(open) => open.pings.latency(),
+            _ => None,
+        }
+    }
     pub(crate) fn detach_reader(&mut self) {
         if let WsConnectionInner::Open(open) = self {
             open.reader_is_attached = false;
@@ -205,7 +289,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsConnectionInner<T> {
     }
     pub(crate) fn detach_writer(&mut self) {
         if let WsConnectionInner::Open(open) = self {
-            open.encode_state.end_message(true);
+            open.encode_state.end_message(&open.config);
         }
     }
 }
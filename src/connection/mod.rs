@@ -1,60 +1,145 @@
+mod buffered;
+mod clock;
+mod close;
 mod config;
 mod decode;
 mod encode;
 mod inner;
 mod open;
+mod ping;
 mod reader;
 mod send;
+mod sink;
+mod split;
 mod waker;
 mod writer;
 
+#[cfg(feature = "async-io")]
+pub use crate::connection::clock::AsyncIoClock;
+#[cfg(feature = "tokio")]
+pub use crate::connection::clock::TokioClock;
+pub use crate::connection::clock::{VirtualClock, WsClock, WsSleep};
+pub use crate::connection::close::{WsClose, WsCloseInitiator, WsCloseStatus};
 pub use crate::connection::config::WsConfig;
+pub use crate::connection::ping::WsPing;
 pub use crate::connection::reader::WsMessageReader;
 pub use crate::connection::send::WsSend;
+pub use crate::connection::split::{ReuniteError, WsReceiver, WsSender};
 pub use crate::connection::writer::WsMessageWriter;
 
 use crate::connection::inner::WsConnectionInner;
-use crate::connection::waker::{new_waker, Wakers};
-use crate::frame::{FrameDecodeError, WsDataFrameKind};
-use crate::message::WsMessageKind;
+use crate::connection::waker::{Parent, Wakers};
+use crate::frame::{CloseBodyError, FrameDecodeError, WsDataFrameKind};
+use crate::http::WsHandshakeInfo;
+use crate::message::{WsMessage, WsMessageKind};
 use futures::prelude::*;
-use std::ops::DerefMut;
+use std::io;
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 pub struct WsConnection<T: AsyncRead + AsyncWrite + Unpin> {
-    parent: Arc<Mutex<(WsConnectionInner<T>, Wakers)>>,
+    parent: Parent<T>,
+    handshake: Option<Arc<WsHandshakeInfo>>,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> WsConnection<T> {
     pub fn with_config(transport: T, config: WsConfig) -> Self {
+        Self::with_buffered(transport, config, Vec::new())
+    }
+    // Like `with_config`, for transports from which bytes following the handshake were read
+    // already. These are processed before reading from `transport`.
+    pub fn with_buffered(transport: T, config: WsConfig, buffered: Vec<u8>) -> Self {
         Self {
             parent: Arc::new(Mutex::new((
-                WsConnectionInner::with_config(transport, config),
+                WsConnectionInner::with_config(transport, config, buffered),
                 Wakers::default(),
             ))),
+            handshake: None,
         }
     }
+    // Attaches what is known from the handshake, which `client::connect` and `server::accept` do
+    // already.
+    pub fn with_handshake(mut self, handshake: WsHandshakeInfo) -> Self {
+        self.handshake = Some(Arc::new(handshake));
+        self
+    }
+    pub fn handshake(&self) -> Option<&WsHandshakeInfo> {
+        self.handshake.as_deref()
+    }
     pub fn send(&self, kind: WsMessageKind) -> WsSend<T> {
         WsSend::new(&self.parent, kind)
     }
+    // Like `send`, but the message is sent as a single frame with a payload of exactly `len`
+    // bytes, which are written to the transport without buffering. Writing more than `len` bytes
+    // fails, closing the writer before `len` bytes were written fails the connection. If `len`
+    // doesn't fit in 63 bits, the writer fails with `InvalidInput` without sending anything.
+    pub fn send_sized(&self, kind: WsMessageKind, len: u64) -> WsSend<T> {
+        WsSend::new_sized(&self.parent, kind, len)
+    }
+    pub async fn send_text(&self, text: &str) -> io::Result<()> {
+        self.send(WsMessageKind::Text)
+            .send_payload(text.as_bytes())
+            .await
+    }
+    pub async fn send_binary(&self, data: &[u8]) -> io::Result<()> {
+        self.send(WsMessageKind::Binary).send_payload(data).await
+    }
+    // Receives the next complete message. Returns `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<io::Result<WsMessage>> {
+        Some(self.next().await?.read_message().await)
+    }
+    // Starts the close handshake. The returned future resolves with the close status once the
+    // peer's close frame was received, or fails with `Timeout` after `WsConfig::close_timeout`.
+    // Fails with `InvalidInput` without touching the connection if `code` may not be sent in a
+    // close frame.
+    pub fn close(&self, code: u16, reason: &str) -> WsClose<T> {
+        WsClose::new(&self.parent, code, reason)
+    }
+    // Sends a ping and resolves with the round trip time once the matching pong arrives. Fails
+    // with `InvalidInput` without touching the connection if `payload` exceeds 125 bytes.
+    pub fn ping(&self, payload: &[u8]) -> WsPing<T> {
+        WsPing::new(&self.parent, payload)
+    }
+    // Smoothed round trip time of all pings answered so far, including keepalive pings.
+    pub fn latency(&self) -> Option<Duration> {
+        self.parent.lock().unwrap().0.latency()
+    }
     pub fn err(&self) -> Option<Arc<WsConnectionError>> {
         self.parent.lock().unwrap().0.err()
     }
+    // The close status after a clean close handshake, `None` while open or after an error.
+    pub fn close_status(&self) -> Option<WsCloseStatus> {
+        self.parent.lock().unwrap().0.close_status()
+    }
+    // Splits the connection into a receiving and a cloneable sending half. The connection stays
+    // open until both halves (and all clones of the sender) are dropped.
+    pub fn split(self) -> (WsReceiver<T>, WsSender<T>) {
+        (
+            WsReceiver::new(self.parent.clone(), self.handshake.clone()),
+            WsSender::new(self.parent, self.handshake),
+        )
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>
+    WsConnection<tokio_util::compat::Compat<T>>
+{
+    // Wraps a transport implementing the tokio io traits. Use `TokioClock` in the config to avoid
+    // depending on the async-io reactor.
+    pub fn from_tokio(transport: T, config: WsConfig) -> Self {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        Self::with_config(transport.compat(), config)
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> Stream for WsConnection<T> {
     type Item = WsMessageReader<T>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut guard = self.parent.lock().unwrap();
-        let (inner, wakers) = guard.deref_mut();
-        wakers.stream_waker = Some(cx.waker().clone());
-        let waker = new_waker(Arc::downgrade(&self.parent));
-        inner
-            .poll_next_reader(&mut Context::from_waker(&waker))
-            .map(|o| o.map(|kind| WsMessageReader::new(kind, &self.parent)))
+        WsMessageReader::poll_next(&self.parent, cx)
     }
 }
 
@@ -72,6 +157,32 @@ pub enum WsConnectionError {
     Timeout,
     #[error("unexpected frame kind {0}")]
     UnexpectedFrameKind(WsDataFrameKind),
+    #[error("incoming message exceeds configured limits")]
+    MessageTooBig,
+    #[error("unexpected rsv bits")]
+    UnexpectedRsv,
+    #[error("invalid extension data")]
+    InvalidExtensionData,
+}
+
+impl WsConnectionError {
+    // The close code sent to the peer when this error fails the connection.
+    pub fn close_code(&self) -> Option<u16> {
+        match self {
+            WsConnectionError::InvalidUtf8 | WsConnectionError::IncompleteUtf8 => Some(1007),
+            WsConnectionError::FrameDecodeError(FrameDecodeError::Io(_)) => None,
+            WsConnectionError::FrameDecodeError(FrameDecodeError::InvalidCloseBody(
+                CloseBodyError::InvalidUtf8,
+            )) => Some(1007),
+            WsConnectionError::FrameDecodeError(_) => Some(1002),
+            WsConnectionError::UnexpectedFrameKind(_) | WsConnectionError::UnexpectedRsv => {
+                Some(1002)
+            }
+            WsConnectionError::InvalidExtensionData => Some(1007),
+            WsConnectionError::MessageTooBig => Some(1009),
+            WsConnectionError::Io(_) | WsConnectionError::Timeout => None,
+        }
+    }
 }
 
 impl From<WsDataFrameKind> for WsConnectionError {
@@ -1,8 +1,12 @@
+use crate::connection::buffered::Buffered;
+use crate::connection::clock::Deadline;
+use crate::connection::close::{CloseState, WsCloseInitiator, WsCloseStatus};
 use crate::connection::decode::{DecodeReady, DecodeState};
 use crate::connection::encode::{EncodeReady, EncodeState};
+use crate::connection::ping::PingState;
 use crate::connection::{WsConfig, WsConnectionError};
+use crate::extension::{split_extensions, ExtensionDecoder};
 use crate::frame::{WsControlFrame, WsControlFrameKind, WsControlFramePayload};
-use async_io::Timer;
 use futures::prelude::*;
 use std::io;
 use std::pin::Pin;
@@ -19,24 +23,52 @@ pub(crate) enum OpenReady {
 
 pub(crate) struct Open<T: AsyncRead + AsyncWrite + Unpin> {
     pub(crate) config: WsConfig,
-    pub(crate) transport: T,
+    pub(crate) transport: Buffered<T>,
     pub(crate) reader_is_attached: bool,
-    timeout: Option<(Timer, bool)>,
+    keepalive: Option<Deadline>,
+    awaiting_pong: bool,
+    idle: Option<Deadline>,
+    closing: Option<Deadline>,
     pub decode_state: DecodeState,
     pub encode_state: EncodeState,
-    pub received_close: Option<WsControlFramePayload>,
+    pub close_state: CloseState,
+    pub close_status: Option<WsCloseStatus>,
+    pub pings: PingState,
+    extensions: Option<ExtensionDecoder>,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> Open<T> {
-    pub(crate) fn with_config(transport: T, config: WsConfig) -> Self {
+    pub(crate) fn with_config(transport: T, mut config: WsConfig, buffered: Vec<u8>) -> Self {
+        // Data frames need room for the frame head and at least one byte of payload.
+        let min_frame_size = match config.mask {
+            true => 7,
+            false => 3,
+        };
+        config.max_outgoing_frame_size = config.max_outgoing_frame_size.max(min_frame_size);
+        let keepalive = config
+            .ping_interval
+            .map(|d| Deadline::new(&*config.clock, d));
+        let idle = config
+            .idle_timeout
+            .map(|d| Deadline::new(&*config.clock, d));
+        let (encoder, decoder) = split_extensions(
+            std::mem::take(&mut config.extensions),
+            config.max_message_size,
+        );
         Self {
             config,
-            transport,
+            transport: Buffered::new(transport, buffered),
             reader_is_attached: false,
-            timeout: None,
+            keepalive,
+            awaiting_pong: false,
+            idle,
+            closing: None,
             decode_state: DecodeState::new(),
-            encode_state: EncodeState::new(),
-            received_close: None,
+            encode_state: EncodeState::new(encoder),
+            close_state: CloseState::None,
+            close_status: None,
+            pings: PingState::default(),
+            extensions: decoder,
         }
     }
     pub(crate) fn take_rx_err(&mut self) -> Option<WsConnectionError> {
@@ -45,37 +77,111 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Open<T> {
     pub(crate) fn take_tx_err(&mut self) -> Option<WsConnectionError> {
         self.encode_state.take_err()
     }
-    fn check_timeout<U>(&mut self, cx: &mut Context, e: U) -> Poll<U> {
-        let ping_timer = match &mut self.timeout {
-            None => self
-                .timeout
-                .insert((Timer::interval(self.config.timeout), false)),
-            Some(ping_timer) => ping_timer,
+    pub(crate) fn close(&mut self, payload: WsControlFramePayload) {
+        if let CloseState::None = self.close_state {
+            self.close_state.queue(payload);
+        }
+    }
+    fn unqueue_close(&mut self) {
+        if let Some(payload) = self.close_state.unqueue() {
+            self.encode_state.queue_control(WsControlFrame {
+                kind: WsControlFrameKind::Close,
+                payload,
+            });
+            let clock = &*self.config.clock;
+            let close_timeout = self.config.close_timeout;
+            self.closing
+                .get_or_insert_with(|| Deadline::new(clock, close_timeout));
+        }
+    }
+    // Fails the connection if the close handshake didn't complete in time. The transport is shut
+    // down on a best-effort basis, it is dropped along with the connection state anyway.
+    fn check_close_timeout(&mut self, cx: &mut Context) -> bool {
+        let closing = match &mut self.closing {
+            Some(closing) => closing,
+            None => return false,
         };
-        if let Poll::Ready(_) = Pin::new(&mut ping_timer.0).poll_next(cx) {
-            if ping_timer.1 {
-                self.decode_state.set_err(WsConnectionError::Timeout);
-                return Poll::Ready(e);
+        if closing.poll(&*self.config.clock, cx).is_pending() {
+            return false;
+        }
+        self.closing = None;
+        self.decode_state.set_err(WsConnectionError::Timeout);
+        let _ = Pin::new(&mut self.transport).poll_close(cx);
+        true
+    }
+    // Restarts the keepalive timer, called whenever something is received.
+    fn reset_keepalive(&mut self) {
+        if let (Some(keepalive), Some(d)) = (&mut self.keepalive, self.config.ping_interval) {
+            keepalive.reset(&*self.config.clock, d);
+        }
+        self.awaiting_pong = false;
+    }
+    // Restarts the idle timeout, called whenever a message is sent or received.
+    pub(crate) fn reset_idle(&mut self) {
+        let clock = &*self.config.clock;
+        match (&mut self.idle, self.config.idle_timeout) {
+            (Some(idle), Some(d)) => idle.reset(clock, d),
+            (idle, d) => *idle = d.map(|d| Deadline::new(clock, d)),
+        }
+    }
+    fn check_timeout<U>(&mut self, cx: &mut Context, e: U) -> Poll<U> {
+        let clock = &*self.config.clock;
+        if let Some(keepalive) = &mut self.keepalive {
+            while keepalive.poll(clock, cx).is_ready() {
+                if self.awaiting_pong {
+                    self.decode_state.set_err(WsConnectionError::Timeout);
+                    return Poll::Ready(e);
+                }
+                self.encode_state.queue_control(WsControlFrame {
+                    kind: WsControlFrameKind::Ping,
+                    payload: self.pings.start_keepalive(),
+                });
+                keepalive.reset(clock, self.config.pong_timeout);
+                self.awaiting_pong = true;
+            }
+        }
+        if let Some(idle) = &mut self.idle {
+            if idle.poll(clock, cx).is_ready() {
+                self.idle = None;
+                self.close(WsControlFramePayload::close(1001, "idle timeout"));
+                self.unqueue_close();
             }
-            self.encode_state
-                .queue_control(WsControlFrame::new(WsControlFrameKind::Ping, &[]));
-            ping_timer.1 = true;
         }
         Poll::Pending
     }
+    // Polls the encoder and stamps the pings it flushed for round trip time measurement.
+    fn poll_encode(&mut self, cx: &mut Context) -> Poll<EncodeReady> {
+        let p = self
+            .encode_state
+            .poll(&mut self.transport, cx, &self.config);
+        let n = self.encode_state.take_flushed_pings();
+        if n > 0 {
+            self.pings.flushed(n, self.config.clock.now());
+        }
+        p
+    }
     pub(crate) fn poll(&mut self, cx: &mut Context) -> (Poll<OpenReady>, Poll<EncodeReady>) {
         loop {
-            let pd = self.decode_state.poll(&mut self.transport, cx);
+            self.unqueue_close();
+            let pd = self.decode_state.poll(
+                &mut self.transport,
+                cx,
+                &self.config,
+                self.extensions.as_ref(),
+            );
             let pd = match pd {
                 Poll::Pending => self.check_timeout(cx, OpenReady::Error),
                 Poll::Ready(DecodeReady::MessageData) => {
                     if !self.reader_is_attached {
-                        match self
-                            .decode_state
-                            .poll_read(&mut self.transport, cx, &mut [0u8; 1300])
-                        {
+                        match self.decode_state.poll_read(
+                            &mut self.transport,
+                            cx,
+                            &mut [0u8; 1300],
+                            self.extensions.as_mut(),
+                            &self.config,
+                        ) {
                             Poll::Ready(_) => {
-                                self.timeout.take();
+                                self.reset_keepalive();
                                 continue;
                             }
                             Poll::Pending => self.check_timeout(cx, OpenReady::Error),
@@ -85,7 +191,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Open<T> {
                     }
                 }
                 Poll::Ready(DecodeReady::MessageEnd) => {
-                    self.timeout.take();
+                    self.reset_keepalive();
                     if !self.reader_is_attached {
                         self.decode_state.take_message_end();
                         self.reader_is_attached = false;
@@ -95,31 +201,53 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Open<T> {
                     }
                 }
                 Poll::Ready(DecodeReady::MessageStart) => {
-                    self.timeout.take();
+                    self.reset_keepalive();
+                    self.reset_idle();
+                    if !self.close_state.open_for_sending() {
+                        // Discard incoming messages while waiting for the close frame.
+                        self.decode_state.take_message_start();
+                        continue;
+                    }
                     Poll::Ready(OpenReady::MessageStart)
                 }
-                Poll::Ready(DecodeReady::Error) => Poll::Ready(OpenReady::Error),
+                Poll::Ready(DecodeReady::Error) => {
+                    // Send a close frame on a best-effort basis before failing the connection.
+                    if let Some(payload) = self.decode_state.err_close_payload() {
+                        self.close(payload);
+                        self.unqueue_close();
+                    }
+                    Poll::Ready(OpenReady::Error)
+                }
                 Poll::Ready(DecodeReady::Done) => Poll::Ready(OpenReady::Done),
-                Poll::Ready(DecodeReady::Control(_)) => {
-                    self.timeout.take();
+                Poll::Ready(DecodeReady::Control(kind)) => {
+                    self.reset_keepalive();
                     let mut control = self.decode_state.take_control().unwrap();
-                    match control.kind() {
+                    match kind {
                         WsControlFrameKind::Ping => {
                             control.kind = WsControlFrameKind::Pong;
                             self.encode_state.queue_control(control);
                         }
-                        WsControlFrameKind::Pong => {}
+                        WsControlFrameKind::Pong => {
+                            let now = self.config.clock.now();
+                            self.pings.receive_pong(control.payload(), now)
+                        }
                         WsControlFrameKind::Close => {
-                            self.received_close = Some(control.payload);
-                            self.encode_state.queue_control(control)
+                            let initiated_by = self
+                                .close_state
+                                .initiator()
+                                .unwrap_or(WsCloseInitiator::Remote);
+                            self.close_state.receive(control.payload);
+                            self.close_status =
+                                Some(WsCloseStatus::new(&control.payload, initiated_by));
                         }
                     }
                     continue;
                 }
             };
-            let pe = self
-                .encode_state
-                .poll(&mut self.transport, cx, self.config.mask);
+            if !matches!(pd, Poll::Ready(OpenReady::Error)) && self.check_close_timeout(cx) {
+                return (Poll::Ready(OpenReady::Error), Poll::Pending);
+            }
+            let pe = self.poll_encode(cx);
             return (pd, pe);
         }
     }
@@ -132,13 +260,22 @@ impl<T: AsyncRead + AsyncWrite + Unpin> Open<T> {
             let (pd, _pe) = self.poll(cx);
             return match pd {
                 Poll::Ready(OpenReady::MessageData) => {
-                    match self.decode_state.poll_read(&mut self.transport, cx, buf) {
+                    match self.decode_state.poll_read(
+                        &mut self.transport,
+                        cx,
+                        buf,
+                        self.extensions.as_mut(),
+                        &self.config,
+                    ) {
                         Poll::Ready(n) => match n {
                             0 => continue,
                             n => Poll::Ready(Ok(n)),
                         },
                         Poll::Pending => {
-                            self.check_timeout(cx, Err(io::ErrorKind::BrokenPipe.into()))
+                            let p = self.check_timeout(cx, Err(io::ErrorKind::BrokenPipe.into()));
+                            // Keepalive pings or the idle close frame may have been queued.
+                            let _ = self.poll_encode(cx);
+                            p
                         }
                     }
                 }
@@ -0,0 +1,173 @@
+use crate::connection::waker::{new_waker, Parent, Wakers};
+use crate::frame::WsControlFramePayload;
+use futures::{AsyncRead, AsyncWrite, Future};
+use std::io;
+use std::ops::DerefMut;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+pub struct WsPing<T: AsyncRead + AsyncWrite + Unpin> {
+    // `None` if the payload doesn't fit in a control frame.
+    payload: Option<WsControlFramePayload>,
+    id: Option<u64>,
+    parent: Parent<T>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> WsPing<T> {
+    pub(crate) fn new(parent: &Parent<T>, payload: &[u8]) -> Self {
+        Self {
+            payload: Some(payload)
+                .filter(|payload| payload.len() <= 125)
+                .map(WsControlFramePayload::new),
+            id: None,
+            parent: parent.clone(),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Future for WsPing<T> {
+    type Output = io::Result<Duration>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.deref_mut();
+        let payload = match this.payload {
+            Some(payload) => payload,
+            None => {
+                let err = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "ping payload exceeds 125 bytes",
+                );
+                return Poll::Ready(Err(err));
+            }
+        };
+        let mut guard = this.parent.lock().unwrap();
+        let (inner, wakers) = guard.deref_mut();
+        Wakers::register(&mut wakers.ping_wakers, cx.waker());
+        let waker = new_waker(Arc::downgrade(&this.parent));
+        let p = inner.poll_ping(&mut Context::from_waker(&waker), payload, &mut this.id);
+        wakers.wake_on_err(&p);
+        p
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Drop for WsPing<T> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.parent.lock().unwrap().0.cancel_ping(id);
+        }
+    }
+}
+
+struct PendingPing {
+    // Keepalive pings have no id, nobody waits for their round trip time.
+    id: Option<u64>,
+    payload: WsControlFramePayload,
+    // Set once the ping frame was flushed to the transport.
+    sent: Option<Instant>,
+    rtt: Option<Duration>,
+}
+
+// Matches incoming pongs to outstanding pings and keeps a smoothed round trip time.
+#[derive(Default)]
+pub(crate) struct PingState {
+    pending: Vec<PendingPing>,
+    next_id: u64,
+    next_keepalive: u64,
+    latency: Option<Duration>,
+}
+
+// Prefix of keepalive ping payloads, followed by a counter.
+const KEEPALIVE_TAG: &[u8] = b"async-ws keepalive ";
+
+impl PingState {
+    // Pings have to be queued in the same order as they are started, so `sent` can be stamped.
+    pub(crate) fn start(&mut self, payload: WsControlFramePayload) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.push(Some(id), payload);
+        id
+    }
+    // Keepalive pings carry a tagged counter and skip payloads of pings that are still pending,
+    // so their pongs are not confused with those of application pings.
+    pub(crate) fn start_keepalive(&mut self) -> WsControlFramePayload {
+        let payload = loop {
+            let mut data = KEEPALIVE_TAG.to_vec();
+            data.extend_from_slice(&self.next_keepalive.to_be_bytes());
+            self.next_keepalive += 1;
+            let payload = WsControlFramePayload::new(&data);
+            if !self
+                .pending
+                .iter()
+                .any(|ping| ping.payload.data() == payload.data())
+            {
+                break payload;
+            }
+        };
+        self.push(None, payload);
+        payload
+    }
+    fn push(&mut self, id: Option<u64>, payload: WsControlFramePayload) {
+        self.pending.push(PendingPing {
+            id,
+            payload,
+            sent: None,
+            rtt: None,
+        })
+    }
+    // Stamps the oldest `n` pings that were not sent yet.
+    pub(crate) fn flushed(&mut self, n: usize, now: Instant) {
+        for ping in self
+            .pending
+            .iter_mut()
+            .filter(|ping| ping.sent.is_none())
+            .take(n)
+        {
+            ping.sent = Some(now);
+        }
+    }
+    // A peer only has to answer the most recent of several pings, so a pong also completes all
+    // pings that were sent before the one it matches.
+    pub(crate) fn receive_pong(&mut self, payload: &[u8], now: Instant) {
+        let pos = self.pending.iter().position(|ping| {
+            ping.sent.is_some() && ping.rtt.is_none() && ping.payload.data() == payload
+        });
+        if let Some(pos) = pos {
+            let rtt = now - self.pending[pos].sent.unwrap();
+            self.latency = Some(match self.latency {
+                None => rtt,
+                Some(latency) => (latency * 7 + rtt) / 8,
+            });
+            let mut n = 0;
+            self.pending.retain_mut(|ping| {
+                n += 1;
+                if let (true, Some(sent), None) = (n <= pos + 1, ping.sent, ping.rtt) {
+                    ping.rtt = Some(now - sent);
+                    return ping.id.is_some();
+                }
+                true
+            });
+        }
+    }
+    pub(crate) fn take_rtt(&mut self, id: u64) -> Option<Duration> {
+        let pos = self
+            .pending
+            .iter()
+            .position(|ping| ping.id == Some(id) && ping.rtt.is_some())?;
+        self.pending.remove(pos).rtt
+    }
+    // A cancelled ping that is still queued keeps its entry, so later pings are stamped correctly.
+    pub(crate) fn cancel(&mut self, id: u64) {
+        self.pending.retain_mut(|ping| {
+            if ping.id != Some(id) {
+                return true;
+            }
+            ping.id = None;
+            ping.sent.is_none()
+        })
+    }
+    pub(crate) fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+}
@@ -1,31 +1,58 @@
-use crate::connection::waker::{new_waker, Wakers};
-use crate::connection::WsConnectionInner;
-use crate::message::WsMessageKind;
-use futures::{AsyncRead, AsyncWrite};
+use crate::connection::waker::{new_waker, Parent};
+use crate::message::{WsMessage, WsMessageKind};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite};
 use std::io;
 use std::ops::DerefMut;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 pub struct WsMessageReader<T: AsyncRead + AsyncWrite + Unpin> {
     kind: WsMessageKind,
-    parent: Option<Arc<Mutex<(WsConnectionInner<T>, Wakers)>>>,
+    parent: Option<Parent<T>>,
+    // Whether part of the message was already read through `AsyncRead`.
+    partially_read: bool,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> WsMessageReader<T> {
-    pub(crate) fn new(
-        kind: WsMessageKind,
-        parent: &Arc<Mutex<(WsConnectionInner<T>, Wakers)>>,
-    ) -> Self {
+    pub(crate) fn new(kind: WsMessageKind, parent: &Parent<T>) -> Self {
         Self {
             kind,
             parent: Some(parent.clone()),
+            partially_read: false,
         }
     }
     pub fn kind(&self) -> WsMessageKind {
         self.kind
     }
+    // Reads the remaining message into memory. The size is bounded by
+    // [max_message_size][`crate::connection::WsConfig::max_message_size`].
+    pub async fn read_message(mut self) -> io::Result<WsMessage> {
+        let whole = !self.partially_read;
+        let mut buffer = Vec::new();
+        self.read_to_end(&mut buffer).await?;
+        Ok(match (self.kind, whole) {
+            (WsMessageKind::Binary, _) => WsMessage::Binary(buffer),
+            // Safety: the payload of text messages is validated while decoding and the read
+            // would have failed on invalid or incomplete utf8.
+            (WsMessageKind::Text, true) => {
+                WsMessage::Text(unsafe { String::from_utf8_unchecked(buffer) })
+            }
+            (WsMessageKind::Text, false) => WsMessage::Text(
+                String::from_utf8(buffer)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            ),
+        })
+    }
+    pub(crate) fn poll_next(parent: &Parent<T>, cx: &mut Context<'_>) -> Poll<Option<Self>> {
+        let mut guard = parent.lock().unwrap();
+        let (inner, wakers) = guard.deref_mut();
+        wakers.stream_waker = Some(cx.waker().clone());
+        let waker = new_waker(Arc::downgrade(parent));
+        inner
+            .poll_next_reader(&mut Context::from_waker(&waker))
+            .map(|o| o.map(|kind| WsMessageReader::new(kind, parent)))
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsMessageReader<T> {
@@ -34,10 +61,11 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsMessageReader<T> {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        if buf.len() == 0 {
+        if buf.is_empty() {
             return Poll::Ready(Ok(0));
         }
-        if let Some(parent) = &self.parent {
+        let this = self.deref_mut();
+        if let Some(parent) = &this.parent {
             let waker = new_waker(Arc::downgrade(parent));
             let mut guard = parent.lock().unwrap();
             let (inner, wakers) = guard.deref_mut();
@@ -55,14 +83,28 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsMessageReader<T> {
             if n == 0 {
                 inner.detach_reader();
                 drop(guard);
-                self.parent.take();
+                this.parent.take();
             }
+            this.partially_read = true;
             return Poll::Ready(Ok(n));
         }
         Poll::Ready(Ok(0))
     }
 }
 
+#[cfg(feature = "tokio")]
+impl<T: AsyncRead + AsyncWrite + Unpin> tokio::io::AsyncRead for WsMessageReader<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let n = futures::ready!(AsyncRead::poll_read(self, cx, buf.initialize_unfilled()))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
 impl<T: AsyncRead + AsyncWrite + Unpin> Drop for WsMessageReader<T> {
     fn drop(&mut self) {
         if let Some(parent) = self.parent.take() {
@@ -1,25 +1,32 @@
-use crate::connection::waker::{new_waker, Wakers};
+use crate::connection::waker::{new_waker, Parent, Wakers};
 use crate::connection::writer::WsMessageWriter;
-use crate::connection::{WsConnectionError, WsConnectionInner};
+use crate::connection::WsConnectionError;
 use crate::message::WsMessageKind;
-use futures::{AsyncRead, AsyncWrite, Future};
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt, Future};
+use std::io;
 use std::ops::DerefMut;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 pub struct WsSend<T: AsyncRead + AsyncWrite + Unpin> {
     kind: WsMessageKind,
-    parent: Arc<Mutex<(WsConnectionInner<T>, Wakers)>>,
+    len: Option<u64>,
+    parent: Parent<T>,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> WsSend<T> {
-    pub(crate) fn new(
-        parent: &Arc<Mutex<(WsConnectionInner<T>, Wakers)>>,
-        kind: WsMessageKind,
-    ) -> Self {
+    pub(crate) fn new(parent: &Parent<T>, kind: WsMessageKind) -> Self {
         Self {
             kind,
+            len: None,
+            parent: parent.clone(),
+        }
+    }
+    pub(crate) fn new_sized(parent: &Parent<T>, kind: WsMessageKind, len: u64) -> Self {
+        Self {
+            kind,
+            len: Some(len),
             parent: parent.clone(),
         }
     }
@@ -29,17 +36,31 @@ impl<T: AsyncRead + AsyncWrite + Unpin> WsSend<T> {
     pub fn err(&self) -> Option<Arc<WsConnectionError>> {
         self.parent.lock().unwrap().0.err()
     }
+    // Sends `payload` as a complete message.
+    pub(crate) async fn send_payload(self, payload: &[u8]) -> io::Result<()> {
+        let mut writer = match self.await {
+            Some(writer) => writer,
+            None => return Err(io::ErrorKind::BrokenPipe.into()),
+        };
+        writer.write_all(payload).await?;
+        writer.close().await
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> Future for WsSend<T> {
     type Output = Option<WsMessageWriter<T>>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The extended payload length of a frame head has 63 bits.
+        if matches!(self.len, Some(len) if len > i64::MAX as u64) {
+            let reason = "sized message length exceeds the maximum frame payload length";
+            return Poll::Ready(Some(WsMessageWriter::rejected(self.kind, reason)));
+        }
         let mut guard = self.parent.lock().unwrap();
         let (inner, wakers) = guard.deref_mut();
-        wakers.send_waker = Some(cx.waker().clone());
+        Wakers::register(&mut wakers.send_wakers, cx.waker());
         let waker = new_waker(Arc::downgrade(&self.parent));
-        match inner.poll_next_writer(self.kind, &mut Context::from_waker(&waker)) {
+        match inner.poll_next_writer(self.kind, self.len, &mut Context::from_waker(&waker)) {
             Poll::Ready(Some(_)) => {
                 Poll::Ready(Some(WsMessageWriter::new(self.kind, &self.parent)))
             }
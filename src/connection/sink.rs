@@ -0,0 +1,119 @@
+use crate::connection::waker::{new_waker, Parent, Wakers};
+use crate::frame::WsControlFramePayload;
+use crate::message::WsMessage;
+use futures::{AsyncRead, AsyncWrite};
+use std::io;
+use std::mem::replace;
+use std::ops::DerefMut;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+// Progress of the message handed to a sink, see `WsSender`.
+pub(crate) enum SinkState {
+    Idle,
+    Waiting(WsMessage),
+    Writing(WsMessage, usize),
+    Ending,
+}
+
+impl SinkState {
+    pub(crate) fn start_send(&mut self, message: WsMessage) {
+        match self {
+            Self::Idle => *self = Self::Waiting(message),
+            _ => panic!("start_send called without poll_ready"),
+        }
+    }
+    pub(crate) fn poll_ready<T: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        parent: &Parent<T>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let waker = new_waker(Arc::downgrade(parent));
+        let mut guard = parent.lock().unwrap();
+        let (inner, wakers) = guard.deref_mut();
+        Wakers::register(&mut wakers.sink_wakers, cx.waker());
+        let cx = &mut Context::from_waker(&waker);
+        loop {
+            let p = match replace(self, Self::Idle) {
+                Self::Idle => return Poll::Ready(Ok(())),
+                Self::Waiting(message) => match inner.poll_next_writer(message.kind(), None, cx) {
+                    Poll::Ready(Some(_)) => {
+                        *self = Self::Writing(message, 0);
+                        continue;
+                    }
+                    Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+                    Poll::Pending => {
+                        *self = Self::Waiting(message);
+                        Poll::Pending
+                    }
+                },
+                Self::Writing(message, offset) if offset == message.as_bytes().len() => {
+                    *self = Self::Ending;
+                    continue;
+                }
+                Self::Writing(message, offset) => {
+                    match inner.poll_write(cx, &message.as_bytes()[offset..]) {
+                        Poll::Ready(Ok(n)) => {
+                            *self = Self::Writing(message, offset + n);
+                            continue;
+                        }
+                        Poll::Ready(Err(err)) => {
+                            inner.detach_writer();
+                            Poll::Ready(Err(err))
+                        }
+                        Poll::Pending => {
+                            *self = Self::Writing(message, offset);
+                            Poll::Pending
+                        }
+                    }
+                }
+                Self::Ending => match inner.poll_close_writer(cx) {
+                    Poll::Ready(r) => {
+                        inner.detach_writer();
+                        match r {
+                            Ok(()) => continue,
+                            Err(err) => Poll::Ready(Err(err)),
+                        }
+                    }
+                    Poll::Pending => {
+                        *self = Self::Ending;
+                        Poll::Pending
+                    }
+                },
+            };
+            wakers.wake_on_err(&p);
+            return p;
+        }
+    }
+    pub(crate) fn poll_close<T: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        parent: &Parent<T>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let p @ (Poll::Pending | Poll::Ready(Err(_))) = self.poll_ready(parent, cx) {
+            return p;
+        }
+        let waker = new_waker(Arc::downgrade(parent));
+        let mut guard = parent.lock().unwrap();
+        let (inner, wakers) = guard.deref_mut();
+        let payload = WsControlFramePayload::close(1000, "");
+        match inner.poll_close(&mut Context::from_waker(&waker), payload) {
+            Poll::Ready(r) => {
+                wakers.wake();
+                Poll::Ready(
+                    r.map(drop)
+                        .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err)),
+                )
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+    pub(crate) fn detach<T: AsyncRead + AsyncWrite + Unpin>(&mut self, parent: &Parent<T>) {
+        if let Self::Writing(..) | Self::Ending = replace(self, Self::Idle) {
+            let mut guard = parent.lock().unwrap();
+            let (inner, wakers) = guard.deref_mut();
+            inner.detach_writer();
+            wakers.wake();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::connection::sink::SinkState;
+use crate::connection::waker::Parent;
+use crate::connection::{
+    WsClose, WsCloseStatus, WsConnection, WsConnectionError, WsMessageReader, WsPing, WsSend,
+};
+use crate::http::WsHandshakeInfo;
+use crate::message::{WsMessage, WsMessageKind};
+use futures::{AsyncRead, AsyncWrite, Sink, Stream, StreamExt};
+use std::fmt;
+use std::io;
+use std::ops::DerefMut;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+pub struct WsReceiver<T: AsyncRead + AsyncWrite + Unpin> {
+    parent: Parent<T>,
+    handshake: Option<Arc<WsHandshakeInfo>>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> WsReceiver<T> {
+    pub(crate) fn new(parent: Parent<T>, handshake: Option<Arc<WsHandshakeInfo>>) -> Self {
+        Self { parent, handshake }
+    }
+    pub fn handshake(&self) -> Option<&WsHandshakeInfo> {
+        self.handshake.as_deref()
+    }
+    pub fn err(&self) -> Option<Arc<WsConnectionError>> {
+        self.parent.lock().unwrap().0.err()
+    }
+    pub fn close_status(&self) -> Option<WsCloseStatus> {
+        self.parent.lock().unwrap().0.close_status()
+    }
+    // Receives the next complete message. Returns `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<io::Result<WsMessage>> {
+        Some(self.next().await?.read_message().await)
+    }
+    pub fn is_pair_of(&self, sender: &WsSender<T>) -> bool {
+        Arc::ptr_eq(&self.parent, &sender.parent)
+    }
+    // Joins the halves returned by [split()][`WsConnection::split()`] again. Fails if the sender
+    // belongs to a different connection.
+    pub fn reunite(self, sender: WsSender<T>) -> Result<WsConnection<T>, ReuniteError<T>> {
+        match self.is_pair_of(&sender) {
+            true => Ok(WsConnection {
+                parent: self.parent,
+                handshake: self.handshake,
+            }),
+            false => Err(ReuniteError(self, sender)),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Stream for WsReceiver<T> {
+    type Item = WsMessageReader<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        WsMessageReader::poll_next(&self.parent, cx)
+    }
+}
+
+// Besides sending through [send()][`Self::send()`], the sender is a `Sink` of complete messages.
+// Closing the sink starts the close handshake.
+pub struct WsSender<T: AsyncRead + AsyncWrite + Unpin> {
+    parent: Parent<T>,
+    handshake: Option<Arc<WsHandshakeInfo>>,
+    sink: SinkState,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> WsSender<T> {
+    pub(crate) fn new(parent: Parent<T>, handshake: Option<Arc<WsHandshakeInfo>>) -> Self {
+        Self {
+            parent,
+            handshake,
+            sink: SinkState::Idle,
+        }
+    }
+    pub fn handshake(&self) -> Option<&WsHandshakeInfo> {
+        self.handshake.as_deref()
+    }
+    pub fn send(&self, kind: WsMessageKind) -> WsSend<T> {
+        WsSend::new(&self.parent, kind)
+    }
+    pub fn send_sized(&self, kind: WsMessageKind, len: u64) -> WsSend<T> {
+        WsSend::new_sized(&self.parent, kind, len)
+    }
+    pub async fn send_text(&self, text: &str) -> io::Result<()> {
+        self.send(WsMessageKind::Text)
+            .send_payload(text.as_bytes())
+            .await
+    }
+    pub async fn send_binary(&self, data: &[u8]) -> io::Result<()> {
+        self.send(WsMessageKind::Binary).send_payload(data).await
+    }
+    pub fn close(&self, code: u16, reason: &str) -> WsClose<T> {
+        WsClose::new(&self.parent, code, reason)
+    }
+    pub fn ping(&self, payload: &[u8]) -> WsPing<T> {
+        WsPing::new(&self.parent, payload)
+    }
+    pub fn latency(&self) -> Option<Duration> {
+        self.parent.lock().unwrap().0.latency()
+    }
+    pub fn err(&self) -> Option<Arc<WsConnectionError>> {
+        self.parent.lock().unwrap().0.err()
+    }
+    pub fn close_status(&self) -> Option<WsCloseStatus> {
+        self.parent.lock().unwrap().0.close_status()
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Clone for WsSender<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.parent.clone(), self.handshake.clone())
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Sink<WsMessage> for WsSender<T> {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { parent, sink, .. } = self.deref_mut();
+        sink.poll_ready(parent, cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: WsMessage) -> io::Result<()> {
+        self.sink.start_send(item);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { parent, sink, .. } = self.deref_mut();
+        sink.poll_ready(parent, cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Self { parent, sink, .. } = self.deref_mut();
+        sink.poll_close(parent, cx)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Drop for WsSender<T> {
+    fn drop(&mut self) {
+        self.sink.detach(&self.parent);
+    }
+}
+
+#[derive(thiserror::Error)]
+#[error("tried to reunite halves of different connections")]
+pub struct ReuniteError<T: AsyncRead + AsyncWrite + Unpin>(pub WsReceiver<T>, pub WsSender<T>);
+
+impl<T: AsyncRead + AsyncWrite + Unpin> fmt::Debug for ReuniteError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReuniteError").finish()
+    }
+}
@@ -1,15 +1,20 @@
 use crate::connection::WsConnectionInner;
 use futures::{AsyncRead, AsyncWrite};
 use std::mem::{take, ManuallyDrop};
-use std::sync::{Mutex, Weak};
+use std::sync::{Arc, Mutex, Weak};
 use std::task::{Poll, RawWaker, RawWakerVTable, Waker};
 
+pub(crate) type Parent<T> = Arc<Mutex<(WsConnectionInner<T>, Wakers)>>;
+
 #[derive(Default)]
 pub(crate) struct Wakers {
     pub stream_waker: Option<Waker>,
-    pub send_waker: Option<Waker>,
+    pub send_wakers: Vec<Waker>,
     pub writer_waker: Option<Waker>,
     pub reader_waker: Option<Waker>,
+    pub close_wakers: Vec<Waker>,
+    pub sink_wakers: Vec<Waker>,
+    pub ping_wakers: Vec<Waker>,
 }
 
 impl Wakers {
@@ -19,10 +24,19 @@ impl Wakers {
                 w.wake();
             }
         }
-        take_and_wake(&mut self.send_waker);
+        self.send_wakers.drain(..).for_each(Waker::wake);
         take_and_wake(&mut self.stream_waker);
         take_and_wake(&mut self.writer_waker);
         take_and_wake(&mut self.reader_waker);
+        self.close_wakers.drain(..).for_each(Waker::wake);
+        self.sink_wakers.drain(..).for_each(Waker::wake);
+        self.ping_wakers.drain(..).for_each(Waker::wake);
+    }
+    // Adds a waker for futures that may be polled by several tasks at the same time.
+    pub(crate) fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
     }
     pub(crate) fn wake_on_err<O, E>(&mut self, p: &Poll<Result<O, E>>) {
         if let Poll::Ready(Err(_)) = &p {
@@ -1,26 +1,39 @@
-use crate::connection::waker::{new_waker, Wakers};
-use crate::connection::WsConnectionInner;
+use crate::connection::waker::{new_waker, Parent};
 use crate::message::WsMessageKind;
 use futures::{AsyncRead, AsyncWrite};
 use std::io;
 use std::ops::DerefMut;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 pub struct WsMessageWriter<T: AsyncRead + AsyncWrite + Unpin> {
     kind: WsMessageKind,
-    parent: Option<Arc<Mutex<(WsConnectionInner<T>, Wakers)>>>,
+    parent: Option<Parent<T>>,
+    // Why the message was rejected before it was started, if it was.
+    rejected: Option<&'static str>,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> WsMessageWriter<T> {
-    pub(crate) fn new(
-        kind: WsMessageKind,
-        parent: &Arc<Mutex<(WsConnectionInner<T>, Wakers)>>,
-    ) -> Self {
+    pub(crate) fn new(kind: WsMessageKind, parent: &Parent<T>) -> Self {
         Self {
             kind,
             parent: Some(parent.clone()),
+            rejected: None,
+        }
+    }
+    // A writer for a message that can't be sent, all operations fail with `InvalidInput`.
+    pub(crate) fn rejected(kind: WsMessageKind, reason: &'static str) -> Self {
+        Self {
+            kind,
+            parent: None,
+            rejected: Some(reason),
+        }
+    }
+    fn detached_err(&self) -> io::Error {
+        match self.rejected {
+            Some(reason) => io::Error::new(io::ErrorKind::InvalidInput, reason),
+            None => io::ErrorKind::BrokenPipe.into(),
         }
     }
     pub fn kind(&self) -> WsMessageKind {
@@ -44,7 +57,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsMessageWriter<T> {
                 wakers.wake_on_err(&p);
                 p
             }
-            None => Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe))),
+            None => Poll::Ready(Err(self.detached_err())),
         }
     }
 
@@ -59,7 +72,7 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsMessageWriter<T> {
                 wakers.wake_on_err(&p);
                 p
             }
-            None => Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe))),
+            None => Poll::Ready(Err(self.detached_err())),
         }
     }
 
@@ -79,11 +92,30 @@ impl<T: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsMessageWriter<T> {
                 }
                 p
             }
-            None => Poll::Ready(Err(io::Error::from(io::ErrorKind::BrokenPipe))),
+            None => Poll::Ready(Err(self.detached_err())),
         }
     }
 }
 
+#[cfg(feature = "tokio")]
+impl<T: AsyncRead + AsyncWrite + Unpin> tokio::io::AsyncWrite for WsMessageWriter<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(self, cx)
+    }
+}
+
 impl<T: AsyncRead + AsyncWrite + Unpin> Drop for WsMessageWriter<T> {
     fn drop(&mut self) {
         if let Some(parent) = self.parent.take() {
@@ -0,0 +1,228 @@
+use crate::connection::WsConnectionError;
+use crate::extension::{WsExtension, WsExtensionDecoder, WsExtensionEncoder, WsFlush};
+use std::borrow::Cow;
+use std::fmt;
+use std::io;
+
+// Splits negotiated extensions into the outgoing and incoming transform chains. Extensions
+// claiming no RSV bits are left out, as the peer couldn't tell which messages they transformed.
+pub(crate) fn split_extensions(
+    extensions: Vec<Box<dyn WsExtension>>,
+    max_message_size: usize,
+) -> (Option<ExtensionEncoder>, Option<ExtensionDecoder>) {
+    let mut encoders = Vec::new();
+    let mut decoders = Vec::new();
+    let mut rsv = 0;
+    for extension in extensions {
+        let bits = extension.rsv();
+        if bits == 0 {
+            continue;
+        }
+        let (encoder, decoder) = extension.split();
+        rsv |= bits;
+        encoders.push(encoder);
+        decoders.push((bits, decoder));
+    }
+    if encoders.is_empty() {
+        return (None, None);
+    }
+    let encoder = ExtensionEncoder {
+        encoders,
+        rsv,
+        output: Vec::new(),
+        ending: false,
+    };
+    let decoder = ExtensionDecoder {
+        decoders,
+        rsv,
+        max_message_size: max_message_size as u64,
+        stages: None,
+    };
+    (Some(encoder), Some(decoder))
+}
+
+// Transforms outgoing messages by all extensions in negotiated order.
+pub(crate) struct ExtensionEncoder {
+    encoders: Vec<Box<dyn WsExtensionEncoder>>,
+    rsv: u8,
+    output: Vec<u8>,
+    ending: bool,
+}
+
+impl ExtensionEncoder {
+    // The RSV bits to set on the first frame of transformed messages.
+    pub(crate) fn rsv(&self) -> u8 {
+        self.rsv
+    }
+    fn encode(&mut self, input: &[u8], flush: WsFlush) -> io::Result<()> {
+        let (last, rest) = self.encoders.split_last_mut().unwrap();
+        let mut data = Cow::Borrowed(input);
+        for encoder in rest {
+            let mut output = Vec::new();
+            encoder.encode(&data, flush, &mut output)?;
+            data = Cow::Owned(output);
+        }
+        last.encode(&data, flush, &mut self.output)
+    }
+    pub(crate) fn write(&mut self, input: &[u8]) -> io::Result<()> {
+        self.encode(input, WsFlush::None)
+    }
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.encode(&[], WsFlush::Sync)
+    }
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        self.encode(&[], WsFlush::Finish)?;
+        self.ending = true;
+        Ok(())
+    }
+    // Transformed output that wasn't sent yet.
+    pub(crate) fn available(&self) -> &[u8] {
+        &self.output
+    }
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.output.drain(..n);
+    }
+    pub(crate) fn is_ending(&self) -> bool {
+        self.ending
+    }
+    // Called once all output of a finished message was sent.
+    pub(crate) fn end(&mut self) {
+        debug_assert!(self.output.is_empty());
+        self.ending = false;
+    }
+}
+
+impl fmt::Debug for ExtensionEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtensionEncoder")
+            .field("encoders", &self.encoders.len())
+            .field("rsv", &self.rsv)
+            .field("output", &self.output.len())
+            .field("ending", &self.ending)
+            .finish()
+    }
+}
+
+// Transforms incoming messages by the extensions whose RSV bits are set, in reverse negotiated
+// order. Each stage buffers the output of the previous one, which is limited to
+// `max_message_size` like the output of the last stage.
+pub(crate) struct ExtensionDecoder {
+    decoders: Vec<(u8, Box<dyn WsExtensionDecoder>)>,
+    rsv: u8,
+    max_message_size: u64,
+    stages: Option<Vec<Stage>>,
+}
+
+struct Stage {
+    index: usize,
+    input: Vec<u8>,
+    // Total size of the input passed on from the previous stage.
+    input_len: u64,
+    end: bool,
+    finished: bool,
+}
+
+impl ExtensionDecoder {
+    // The RSV bits claimed by any of the extensions.
+    pub(crate) fn rsv(&self) -> u8 {
+        self.rsv
+    }
+    pub(crate) fn is_started(&self) -> bool {
+        self.stages.is_some()
+    }
+    pub(crate) fn start_message(&mut self, rsv: u8) {
+        let stages = self
+            .decoders
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, (bits, _))| bits & rsv != 0)
+            .map(|(index, _)| Stage {
+                index,
+                input: Vec::new(),
+                input_len: 0,
+                end: false,
+                finished: false,
+            })
+            .collect();
+        self.stages = Some(stages);
+    }
+    pub(crate) fn push(&mut self, input: &[u8]) {
+        if let Some(stage) = self.stages.as_mut().and_then(|stages| stages.first_mut()) {
+            stage.input.extend_from_slice(input);
+        }
+    }
+    // Marks the end of the message payload. Returns false if this already happened.
+    pub(crate) fn end(&mut self) -> bool {
+        match self.stages.as_mut().and_then(|stages| stages.first_mut()) {
+            Some(stage) if !stage.end => {
+                stage.end = true;
+                true
+            }
+            _ => false,
+        }
+    }
+    pub(crate) fn finished(&self) -> bool {
+        match &self.stages {
+            Some(stages) => stages.last().map_or(true, |stage| stage.finished),
+            None => false,
+        }
+    }
+    pub(crate) fn end_message(&mut self) {
+        self.stages = None;
+    }
+    // Writes transformed payload to `buf`. Returns 0 if more payload is needed or the message is
+    // finished.
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> Result<usize, WsConnectionError> {
+        let stages = match &mut self.stages {
+            Some(stages) => stages,
+            None => return Ok(0),
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let mut progress = false;
+            for i in (0..stages.len()).rev() {
+                let (current, next) = stages.split_at_mut(i + 1);
+                let stage = &mut current[i];
+                if stage.finished {
+                    continue;
+                }
+                let mut scratch = [0u8; 1300];
+                let output: &mut [u8] = match next.is_empty() {
+                    true => &mut *buf,
+                    false => &mut scratch,
+                };
+                let (consumed, produced) =
+                    self.decoders[stage.index]
+                        .1
+                        .decode(&stage.input, stage.end, output)?;
+                stage.input.drain(..consumed);
+                if stage.end && stage.input.is_empty() && produced == 0 {
+                    stage.finished = true;
+                    if let Some(next) = next.first_mut() {
+                        next.end = true;
+                    }
+                    progress = true;
+                    continue;
+                }
+                progress |= consumed > 0 || produced > 0;
+                match next.first_mut() {
+                    Some(next) => {
+                        next.input_len += produced as u64;
+                        if next.input_len > self.max_message_size {
+                            return Err(WsConnectionError::MessageTooBig);
+                        }
+                        next.input.extend_from_slice(&output[..produced]);
+                    }
+                    None if produced > 0 => return Ok(produced),
+                    None => {}
+                }
+            }
+            if !progress {
+                return Ok(0);
+            }
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use crate::connection::WsConnectionError;
+use crate::extension::{
+    WsExtension, WsExtensionDecoder, WsExtensionEncoder, WsExtensionOffer, WsFlush, RSV1,
+};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io;
+
+// Trailer of a sync flush, which is stripped from compressed messages (RFC 7692 section 7.2.1).
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Negotiated parameters of the permessage-deflate extension (RFC 7692). Compression always uses
+// a window of 15 bits, so offers restricting the sender window are declined.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeflateParams {
+    pub server_no_context_takeover: bool,
+    pub client_no_context_takeover: bool,
+}
+
+impl DeflateParams {
+    pub const NAME: &'static str = "permessage-deflate";
+
+    // The `Sec-WebSocket-Extensions` element offering (client) or accepting (server) these
+    // parameters.
+    fn header_element(&self) -> String {
+        let mut value = Self::NAME.to_string();
+        if self.server_no_context_takeover {
+            value.push_str("; server_no_context_takeover");
+        }
+        if self.client_no_context_takeover {
+            value.push_str("; client_no_context_takeover");
+        }
+        value
+    }
+    // Accepts a client offer if it can be honored. The context takeover parameters set in `self`
+    // are added to the response, even if the client didn't ask for them.
+    fn accept(&self, offer: &WsExtensionOffer) -> Option<DeflateParams> {
+        if offer.name() != Self::NAME || offer.has_duplicate_params() {
+            return None;
+        }
+        let mut params = *self;
+        for (name, value) in offer.params() {
+            match (name.as_str(), value.as_deref()) {
+                ("server_no_context_takeover", None) => params.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => params.client_no_context_takeover = true,
+                ("server_max_window_bits", Some(bits)) => match parse_window_bits(bits)? {
+                    15 => {}
+                    _ => return None,
+                },
+                ("client_max_window_bits", None) => {}
+                ("client_max_window_bits", Some(bits)) => {
+                    parse_window_bits(bits)?;
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+    // Parses the server response to an offer, which never includes `client_max_window_bits`.
+    fn from_response(response: &WsExtensionOffer) -> Option<DeflateParams> {
+        if response.name() != Self::NAME || response.has_duplicate_params() {
+            return None;
+        }
+        let mut params = DeflateParams::default();
+        for (name, value) in response.params() {
+            match (name.as_str(), value.as_deref()) {
+                ("server_no_context_takeover", None) => params.server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => params.client_no_context_takeover = true,
+                ("server_max_window_bits", Some(bits)) => {
+                    parse_window_bits(bits)?;
+                }
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+    // Whether the compressor (`local`) or decompressor of the given endpoint is reset after each
+    // message.
+    fn no_context_takeover(&self, client: bool, local: bool) -> bool {
+        match client == local {
+            true => self.client_no_context_takeover,
+            false => self.server_no_context_takeover,
+        }
+    }
+}
+
+fn parse_window_bits(bits: &str) -> Option<u8> {
+    if bits.starts_with('0') {
+        return None;
+    }
+    match bits.parse() {
+        Ok(bits @ 8..=15) => Some(bits),
+        _ => None,
+    }
+}
+
+// The permessage-deflate extension. Offers and accepts the context takeover parameters set in
+// `params` in addition to those requested by the peer.
+#[derive(Clone, Debug, Default)]
+pub struct DeflateExtension {
+    params: DeflateParams,
+    client: bool,
+}
+
+impl DeflateExtension {
+    pub fn new(params: DeflateParams) -> Self {
+        Self {
+            params,
+            client: true,
+        }
+    }
+    // The negotiated parameters once the extension was accepted or configured.
+    pub fn params(&self) -> DeflateParams {
+        self.params
+    }
+}
+
+impl WsExtension for DeflateExtension {
+    fn name(&self) -> &str {
+        DeflateParams::NAME
+    }
+    fn rsv(&self) -> u8 {
+        RSV1
+    }
+    fn offer(&self) -> String {
+        self.params.header_element()
+    }
+    fn accept(&mut self, offer: &WsExtensionOffer) -> Option<String> {
+        self.params = self.params.accept(offer)?;
+        self.client = false;
+        Some(self.params.header_element())
+    }
+    fn configure(&mut self, response: &WsExtensionOffer) -> bool {
+        match DeflateParams::from_response(response) {
+            Some(params) => {
+                self.params = params;
+                self.client = true;
+                true
+            }
+            None => false,
+        }
+    }
+    fn split(self: Box<Self>) -> (Box<dyn WsExtensionEncoder>, Box<dyn WsExtensionDecoder>) {
+        let params = self.params;
+        (
+            Box::new(DeflateEncoder::new(
+                params.no_context_takeover(self.client, true),
+            )),
+            Box::new(DeflateDecoder::new(
+                params.no_context_takeover(self.client, false),
+            )),
+        )
+    }
+}
+
+// Compresses outgoing messages. The last bytes of the output are held back until it is known
+// that they aren't part of the trailer, which is stripped at the end of each message.
+struct DeflateEncoder {
+    compress: Compress,
+    no_context_takeover: bool,
+    buffer: Vec<u8>,
+}
+
+impl DeflateEncoder {
+    fn new(no_context_takeover: bool) -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            no_context_takeover,
+            buffer: Vec::new(),
+        }
+    }
+    fn compress(&mut self, mut input: &[u8], flush: FlushCompress) -> io::Result<()> {
+        loop {
+            self.buffer.reserve(input.len() / 2 + 64);
+            let total_in = self.compress.total_in();
+            self.compress
+                .compress_vec(input, &mut self.buffer, flush)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            input = &input[(self.compress.total_in() - total_in) as usize..];
+            if input.is_empty() && self.buffer.len() < self.buffer.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl WsExtensionEncoder for DeflateEncoder {
+    fn encode(&mut self, input: &[u8], flush: WsFlush, output: &mut Vec<u8>) -> io::Result<()> {
+        match flush {
+            WsFlush::None => self.compress(input, FlushCompress::None)?,
+            WsFlush::Sync | WsFlush::Finish => self.compress(input, FlushCompress::Sync)?,
+        }
+        let hold_back = match flush {
+            WsFlush::Finish => {
+                debug_assert!(self.buffer.ends_with(&TAIL));
+                self.buffer.truncate(self.buffer.len() - TAIL.len());
+                if self.no_context_takeover {
+                    self.compress.reset();
+                }
+                0
+            }
+            WsFlush::None | WsFlush::Sync => TAIL.len(),
+        };
+        let n = self.buffer.len().saturating_sub(hold_back);
+        output.extend(self.buffer.drain(..n));
+        Ok(())
+    }
+}
+
+// Decompresses incoming messages from buffered payload.
+struct DeflateDecoder {
+    decompress: Decompress,
+    no_context_takeover: bool,
+    input: Vec<u8>,
+    tail_fed: bool,
+    // Set once a block with BFINAL ended the deflate stream.
+    finished: bool,
+}
+
+impl DeflateDecoder {
+    fn new(no_context_takeover: bool) -> Self {
+        Self {
+            decompress: Decompress::new(false),
+            no_context_takeover,
+            input: Vec::new(),
+            tail_fed: false,
+            finished: false,
+        }
+    }
+    fn inflate(&mut self, buf: &mut [u8]) -> Result<usize, WsConnectionError> {
+        let mut produced = 0;
+        while produced < buf.len() && !self.finished {
+            let (total_in, total_out) = (self.decompress.total_in(), self.decompress.total_out());
+            let status = self
+                .decompress
+                .decompress(&self.input, &mut buf[produced..], FlushDecompress::Sync)
+                .map_err(|_| WsConnectionError::InvalidExtensionData)?;
+            self.finished = status == Status::StreamEnd;
+            let consumed = (self.decompress.total_in() - total_in) as usize;
+            let written = (self.decompress.total_out() - total_out) as usize;
+            self.input.drain(..consumed);
+            produced += written;
+            if consumed == 0 && written == 0 {
+                break;
+            }
+        }
+        Ok(produced)
+    }
+}
+
+impl WsExtensionDecoder for DeflateDecoder {
+    fn decode(
+        &mut self,
+        input: &[u8],
+        end: bool,
+        output: &mut [u8],
+    ) -> Result<(usize, usize), WsConnectionError> {
+        self.input.extend_from_slice(input);
+        if end && !self.tail_fed {
+            self.input.extend_from_slice(&TAIL);
+            self.tail_fed = true;
+        }
+        let produced = self.inflate(output)?;
+        if self.finished {
+            // A sender may end the message with a final block, only the trailer we appended can
+            // follow it. The stream can't continue, so the next message starts a new one.
+            let trailer: &[u8] = if self.tail_fed { &TAIL } else { &[] };
+            if self.input != trailer {
+                return Err(WsConnectionError::InvalidExtensionData);
+            }
+        }
+        if end && produced == 0 {
+            // Data left after the trailer can't be part of the message.
+            if !self.finished && !self.input.is_empty() {
+                return Err(WsConnectionError::InvalidExtensionData);
+            }
+            self.input.clear();
+            self.tail_fed = false;
+            if self.no_context_takeover || self.finished {
+                self.decompress.reset(false);
+            }
+            self.finished = false;
+        }
+        Ok((input.len(), produced))
+    }
+}
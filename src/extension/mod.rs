@@ -0,0 +1,123 @@
+mod chain;
+mod deflate;
+
+pub use crate::extension::deflate::{DeflateExtension, DeflateParams};
+
+pub(crate) use crate::extension::chain::{split_extensions, ExtensionDecoder, ExtensionEncoder};
+
+use crate::connection::WsConnectionError;
+use crate::http::header::{self, is_token};
+use http::HeaderMap;
+use std::io;
+
+// Reserved bits of the frame head, as they appear in its first byte.
+pub const RSV1: u8 = 0x40;
+pub const RSV2: u8 = 0x20;
+pub const RSV3: u8 = 0x10;
+
+// A WebSocket extension (RFC 6455 section 9), negotiated through `Sec-WebSocket-Extensions`.
+// Negotiated extensions transform the payload of messages whose first frame has any of the
+// claimed RSV bits set. Outgoing messages are transformed by all extensions in negotiated order,
+// except for messages sent with `send_sized`, which are sent as is.
+pub trait WsExtension: Send {
+    // The extension token, e.g. `permessage-deflate`.
+    fn name(&self) -> &str;
+    // The RSV bits claimed by this extension, a combination of `RSV1`, `RSV2` and `RSV3`.
+    // Extensions claiming none are never negotiated or applied.
+    fn rsv(&self) -> u8;
+    // The element of the client's `Sec-WebSocket-Extensions` header offering this extension.
+    fn offer(&self) -> String;
+    // Server side: configures the extension from a client offer and returns the element of the
+    // response header, or `None` to decline the offer.
+    fn accept(&mut self, offer: &WsExtensionOffer) -> Option<String>;
+    // Client side: configures the extension from the server response. Returning false fails the
+    // handshake.
+    fn configure(&mut self, response: &WsExtensionOffer) -> bool;
+    // Splits the configured extension into the transforms used by the connection.
+    fn split(self: Box<Self>) -> (Box<dyn WsExtensionEncoder>, Box<dyn WsExtensionDecoder>);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WsFlush {
+    None,
+    // All input so far has to be decodable by the peer once the output is sent.
+    Sync,
+    // The message ends with this input.
+    Finish,
+}
+
+pub trait WsExtensionEncoder: Send {
+    // Transforms outgoing payload and appends the result to `output`. An error fails the
+    // connection.
+    fn encode(&mut self, input: &[u8], flush: WsFlush, output: &mut Vec<u8>) -> io::Result<()>;
+}
+
+pub trait WsExtensionDecoder: Send {
+    // Transforms incoming payload into `output` and returns the number of bytes consumed from
+    // `input` and written to `output`. Once `end` is set, all payload of the message was passed
+    // in and returning no output completes the message.
+    fn decode(
+        &mut self,
+        input: &[u8],
+        end: bool,
+        output: &mut [u8],
+    ) -> Result<(usize, usize), WsConnectionError>;
+}
+
+// An element of a `Sec-WebSocket-Extensions` header: an extension name with its parameters.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WsExtensionOffer {
+    name: String,
+    params: Vec<(String, Option<String>)>,
+}
+
+impl WsExtensionOffer {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn params(&self) -> &[(String, Option<String>)] {
+        &self.params
+    }
+    pub fn has_duplicate_params(&self) -> bool {
+        self.params
+            .iter()
+            .enumerate()
+            .any(|(i, (name, _))| self.params[..i].iter().any(|(n, _)| n == name))
+    }
+    // Parses all `Sec-WebSocket-Extensions` headers in order. Returns `None` if any is malformed.
+    pub(crate) fn parse_headers(headers: &HeaderMap) -> Option<Vec<WsExtensionOffer>> {
+        let mut offers = Vec::new();
+        for offer in header::list(headers, "Sec-WebSocket-Extensions")? {
+            offers.push(Self::parse(offer)?);
+        }
+        Some(offers)
+    }
+    fn parse(offer: &str) -> Option<WsExtensionOffer> {
+        let mut parts = header::split_quoted(offer, b';')?.into_iter();
+        let name = parts.next().filter(|name| is_token(name))?.to_string();
+        let params = parts
+            .map(|param| {
+                let (name, value) = match param.split_once('=') {
+                    Some((name, value)) => (name.trim(), Some(unquote(value.trim())?)),
+                    None => (param, None),
+                };
+                match is_token(name) {
+                    true => Some((name.to_string(), value)),
+                    false => None,
+                }
+            })
+            .collect::<Option<_>>()?;
+        Some(WsExtensionOffer { name, params })
+    }
+}
+
+fn unquote(value: &str) -> Option<String> {
+    let value = match value.strip_prefix('"') {
+        Some(quoted) => quoted.strip_suffix('"')?,
+        None => value,
+    };
+    match is_token(value) {
+        true => Some(value.to_string()),
+        false => None,
+    }
+}
@@ -28,6 +28,12 @@ pub enum FrameDecoderState {
     },
 }
 
+impl Default for FrameDecoderState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FrameDecoderState {
     pub fn new() -> Self {
         Self::Head(FrameHeadDecodeState::new())
@@ -51,6 +57,7 @@ impl FrameDecoderState {
                             return Poll::Ready(Ok(WsFrame::Data(WsDataFrame {
                                 kind: frame_kind,
                                 fin: frame_head.fin,
+                                rsv: frame_head.rsv(),
                                 mask: frame_head.mask,
                                 payload_len: frame_head.payload_len,
                             })))
@@ -31,6 +31,10 @@ impl WsOpcode {
 #[derive(Copy, Clone, Debug)]
 pub struct FrameHead {
     pub fin: bool,
+    // Reserved bits, whose meaning is defined by negotiated extensions.
+    pub rsv1: bool,
+    pub rsv2: bool,
+    pub rsv3: bool,
     pub opcode: WsOpcode,
     pub mask: [u8; 4],
     pub payload_len: u64,
@@ -56,11 +60,10 @@ impl FrameHead {
         if buffer.len() < expected_buffer_len {
             return Err(FrameHeadParseError::Incomplete(expected_buffer_len));
         }
-        let fin = match buffer[0] & 0xF0 {
-            0x00 => false,
-            0x80 => true,
-            _ => return Err(FrameHeadParseError::RsvBit),
-        };
+        let fin = buffer[0] & 0x80 != 0;
+        let rsv1 = buffer[0] & 0x40 != 0;
+        let rsv2 = buffer[0] & 0x20 != 0;
+        let rsv3 = buffer[0] & 0x10 != 0;
         let opcode = match buffer[0] & 0x0F {
             0x0 => WsOpcode::Continuation,
             0x1 => WsOpcode::Text,
@@ -86,12 +89,18 @@ impl FrameHead {
             if !fin {
                 return Err(FrameHeadParseError::FragmentedControl);
             }
+            if rsv1 || rsv2 || rsv3 {
+                return Err(FrameHeadParseError::RsvBit);
+            }
         }
         if payload_len > opcode.frame_kind().max_payload_len() {
             return Err(FrameHeadParseError::PayloadLengthTooLong);
         }
         Ok(FrameHead {
             fin,
+            rsv1,
+            rsv2,
+            rsv3,
             opcode,
             mask,
             payload_len,
@@ -101,18 +110,22 @@ impl FrameHead {
     pub fn len_bytes(&self) -> usize {
         let extra_payload_len_bytes = match self.payload_len {
             0..=125 => 0usize,
-            126..=65536 => 2usize,
+            126..=65535 => 2usize,
             _ => 8usize,
         };
         2 + extra_payload_len_bytes + self.masked() as usize * 4
     }
+    // The reserved bits as they appear in the first byte of the frame head.
+    pub fn rsv(&self) -> u8 {
+        self.rsv1 as u8 * 0x40 + self.rsv2 as u8 * 0x20 + self.rsv3 as u8 * 0x10
+    }
     pub fn masked(&self) -> bool {
         self.mask != [0u8, 0u8, 0u8, 0u8]
     }
     // Writes the frame head to `buffer`.
     // Panics if `buffer` is too small (see [len_bytes()][`Self::len_bytes()`]).
     pub fn encode(&self, buffer: &mut [u8]) {
-        buffer[0] = self.fin as u8 * 0x80;
+        buffer[0] = self.fin as u8 * 0x80 + self.rsv();
         buffer[0] += match self.opcode {
             WsOpcode::Continuation => 0x0,
             WsOpcode::Text => 0x1,
@@ -123,7 +136,7 @@ impl FrameHead {
         };
         buffer[1] = match self.payload_len {
             0..=125 => self.payload_len as u8,
-            126..=65536 => 126u8,
+            126..=65535 => 126u8,
             _ => 127u8,
         };
         match buffer[1] {
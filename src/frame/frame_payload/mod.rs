@@ -7,7 +7,7 @@ pub use decode::*;
 pub fn payload_mask(mask: [u8; 4], mut offset: usize, buffer: &mut [u8]) {
     if mask != [0u8, 0u8, 0u8, 0u8] {
         for byte in buffer.iter_mut() {
-            offset = offset % 4;
+            offset %= 4;
             *byte ^= mask[offset];
             offset += 1;
         }
@@ -18,17 +18,17 @@ pub fn payload_mask(mask: [u8; 4], mut offset: usize, buffer: &mut [u8]) {
 pub fn max_payload_len(masked: bool, max_frame_size: u64) -> u64 {
     match (masked, max_frame_size) {
         (true, 0..=6) => 0,
-        (true, 7..=132) => max_frame_size - 6,
-        (true, 133..=134) => 126,
-        (true, 135..=65542) => max_frame_size - 8,
-        (true, 65543..=65548) => 65536,
-        (true, 65549..=2147483661) => max_frame_size - 14,
+        (true, 7..=131) => max_frame_size - 6,
+        (true, 132..=133) => 125,
+        (true, 134..=65543) => max_frame_size - 8,
+        (true, 65544..=65549) => 65535,
+        (true, 65550..=2147483661) => max_frame_size - 14,
         (false, 0..=2) => 0,
-        (false, 3..=128) => max_frame_size - 2,
-        (false, 129..=130) => 126,
-        (false, 131..=65538) => max_frame_size - 4,
-        (false, 65539..=65544) => 65536,
-        (false, 65545..=2147483657) => max_frame_size - 10,
+        (false, 3..=127) => max_frame_size - 2,
+        (false, 128..=129) => 125,
+        (false, 130..=65539) => max_frame_size - 4,
+        (false, 65540..=65545) => 65535,
+        (false, 65546..=2147483657) => max_frame_size - 10,
         _ => 2147483647,
     }
 }
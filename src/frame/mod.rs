@@ -1,3 +1,5 @@
+// Shadows the `frame_payload::decode` module, whose items are re-exported below.
+#[allow(hidden_glob_reexports)]
 mod decode;
 mod frame_head;
 mod frame_payload;
@@ -32,11 +34,11 @@ impl WsFrame {
         let payload_buffer = &mut buffer[frame_head.len_bytes()..total];
         payload_buffer.copy_from_slice(frame_payload);
         payload_mask(frame_head.mask, 0, payload_buffer);
-        return total;
+        total
     }
     pub fn encode_vec(frame_head: FrameHead, frame_payload: &[u8]) -> Vec<u8> {
         let mut buffer = vec![0u8; frame_head.len_bytes() + frame_payload.len()];
-        WsFrame::encode(frame_head, frame_payload, &mut *buffer);
+        WsFrame::encode(frame_head, frame_payload, &mut buffer);
         buffer
     }
 }
@@ -119,6 +121,7 @@ impl WsControlFrameKind {
 pub struct WsDataFrame {
     pub(crate) kind: WsDataFrameKind,
     pub(crate) fin: bool,
+    pub(crate) rsv: u8,
     pub(crate) mask: [u8; 4],
     pub(crate) payload_len: u64,
 }
@@ -133,6 +136,19 @@ impl WsDataFrame {
     pub fn fin(&self) -> bool {
         self.fin
     }
+    pub fn rsv1(&self) -> bool {
+        self.rsv & 0x40 != 0
+    }
+    pub fn rsv2(&self) -> bool {
+        self.rsv & 0x20 != 0
+    }
+    pub fn rsv3(&self) -> bool {
+        self.rsv & 0x10 != 0
+    }
+    // The reserved bits as they appear in the first byte of the frame head.
+    pub fn rsv(&self) -> u8 {
+        self.rsv
+    }
     pub fn mask(&self) -> [u8; 4] {
         self.mask
     }
@@ -153,7 +169,7 @@ impl WsControlFrame {
         Self { kind, payload }
     }
     pub fn payload(&self) -> &[u8] {
-        &self.payload.data()
+        self.payload.data()
     }
     pub fn kind(&self) -> WsControlFrameKind {
         self.kind
@@ -161,6 +177,9 @@ impl WsControlFrame {
     pub fn head(&self, mask: [u8; 4]) -> FrameHead {
         FrameHead {
             fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
             opcode: self.kind.opcode(),
             mask,
             payload_len: self.payload().len() as u64,
@@ -185,6 +204,20 @@ impl WsControlFramePayload {
             buffer,
         }
     }
+    // Builds a close frame body. The reason is truncated to fit into the 125 byte payload.
+    pub(crate) fn close(code: u16, reason: &str) -> Self {
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut buffer = [0u8; 125];
+        buffer[0..2].copy_from_slice(&code.to_be_bytes());
+        buffer[2..2 + end].copy_from_slice(&reason.as_bytes()[..end]);
+        Self {
+            len: 2 + end as u8,
+            buffer,
+        }
+    }
     pub(crate) fn data(&self) -> &[u8] {
         &self.buffer[0..self.len()]
     }
@@ -199,11 +232,13 @@ impl WsControlFramePayload {
                 let data = self.data();
                 let code = u16::from_be_bytes([data[0], data[1]]);
                 match code {
-                    0..=999 | 1004..=1006 | 1016..=2999 => Err(CloseBodyError::InvalidCode),
-                    code => match std::str::from_utf8(&data[2..]) {
-                        Ok(reason) => Ok(Some((code, reason))),
-                        Err(_) => Err(CloseBodyError::InvalidUtf8),
-                    },
+                    1000..=1003 | 1007..=1014 | 3000..=4999 => {
+                        match std::str::from_utf8(&data[2..]) {
+                            Ok(reason) => Ok(Some((code, reason))),
+                            Err(_) => Err(CloseBodyError::InvalidUtf8),
+                        }
+                    }
+                    _ => Err(CloseBodyError::InvalidCode),
                 }
             }
         }
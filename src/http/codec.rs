@@ -0,0 +1,156 @@
+use futures::prelude::*;
+use http::header::HOST;
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri, Version,
+};
+use std::io;
+
+// Maximum number of headers in a parsed head.
+const MAX_HEADERS: usize = 64;
+
+pub(crate) enum HeadError {
+    Io(io::Error),
+    TooLarge,
+    Invalid(httparse::Error),
+}
+
+impl From<io::Error> for HeadError {
+    fn from(err: io::Error) -> Self {
+        HeadError::Io(err)
+    }
+}
+
+// Reads from `transport` until the end of an HTTP/1.1 head of at most `max_len` bytes. Returns the
+// bytes read and the length of the head. Bytes following the head belong to the next protocol.
+pub(crate) async fn read_head<T: AsyncRead + Unpin>(
+    transport: &mut T,
+    max_len: usize,
+) -> Result<(Vec<u8>, usize), HeadError> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = transport.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let searched = buffer.len().saturating_sub(3);
+        buffer.extend_from_slice(&chunk[..n]);
+        if let Some(i) = buffer[searched..].windows(4).position(|w| w == b"\r\n\r\n") {
+            return match searched + i + 4 {
+                len if len > max_len => Err(HeadError::TooLarge),
+                len => Ok((buffer, len)),
+            };
+        }
+        if buffer.len() >= max_len {
+            return Err(HeadError::TooLarge);
+        }
+    }
+}
+
+// Writes the head of `request` in HTTP/1.1 syntax. The `Host` header is taken from the URI unless
+// set explicitly.
+pub(crate) async fn write_request<T: AsyncWrite + Unpin>(
+    transport: &mut T,
+    request: &Request<()>,
+) -> io::Result<()> {
+    let target = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |target| target.as_str());
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method(), target).into_bytes();
+    if !request.headers().contains_key(HOST) {
+        match request.uri().authority() {
+            Some(authority) => {
+                head.extend_from_slice(format!("host: {}\r\n", authority).as_bytes())
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "request has neither a host header nor an authority",
+                ))
+            }
+        }
+    }
+    write_head(transport, head, request.headers()).await
+}
+
+// Writes the head of `response` in HTTP/1.1 syntax.
+pub(crate) async fn write_response<T: AsyncWrite + Unpin>(
+    transport: &mut T,
+    response: &Response<()>,
+) -> io::Result<()> {
+    let head = format!("HTTP/1.1 {}\r\n", response.status()).into_bytes();
+    write_head(transport, head, response.headers()).await
+}
+
+async fn write_head<T: AsyncWrite + Unpin>(
+    transport: &mut T,
+    mut head: Vec<u8>,
+    headers: &HeaderMap,
+) -> io::Result<()> {
+    for (name, value) in headers {
+        head.extend_from_slice(name.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    transport.write_all(&head).await?;
+    transport.flush().await
+}
+
+pub(crate) fn parse_request(head: &[u8], max_headers: usize) -> Result<Request<()>, HeadError> {
+    let mut headers = vec![httparse::EMPTY_HEADER; max_headers];
+    let mut parsed = httparse::Request::new(&mut headers);
+    match parsed.parse(head).map_err(HeadError::Invalid)? {
+        httparse::Status::Complete(_) => {}
+        httparse::Status::Partial => return Err(HeadError::Invalid(httparse::Error::Token)),
+    }
+    let mut request = Request::new(());
+    *request.method_mut() = parsed
+        .method
+        .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+        .ok_or(HeadError::Invalid(httparse::Error::Token))?;
+    *request.uri_mut() = parsed
+        .path
+        .and_then(|path| path.parse::<Uri>().ok())
+        .ok_or(HeadError::Invalid(httparse::Error::Token))?;
+    *request.version_mut() = version(parsed.version);
+    append_headers(request.headers_mut(), parsed.headers)?;
+    Ok(request)
+}
+
+pub(crate) fn parse_response(head: &[u8]) -> Result<Response<()>, HeadError> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut parsed = httparse::Response::new(&mut headers);
+    match parsed.parse(head).map_err(HeadError::Invalid)? {
+        httparse::Status::Complete(_) => {}
+        httparse::Status::Partial => return Err(HeadError::Invalid(httparse::Error::Status)),
+    }
+    let mut response = Response::new(());
+    *response.status_mut() = parsed
+        .code
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or(HeadError::Invalid(httparse::Error::Status))?;
+    *response.version_mut() = version(parsed.version);
+    append_headers(response.headers_mut(), parsed.headers)?;
+    Ok(response)
+}
+
+fn version(version: Option<u8>) -> Version {
+    match version {
+        Some(0) => Version::HTTP_10,
+        _ => Version::HTTP_11,
+    }
+}
+
+fn append_headers(map: &mut HeaderMap, headers: &[httparse::Header]) -> Result<(), HeadError> {
+    for header in headers {
+        let name = HeaderName::from_bytes(header.name.as_bytes())
+            .map_err(|_| HeadError::Invalid(httparse::Error::HeaderName))?;
+        let value = HeaderValue::from_bytes(header.value)
+            .map_err(|_| HeadError::Invalid(httparse::Error::HeaderValue))?;
+        map.append(name, value);
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+// WebSockets over HTTP/2 through extended CONNECT requests (RFC 8441). The handshake happens on a
+// single stream: the client sends a CONNECT request with the `:protocol` pseudo-header set to
+// `websocket`, and the server accepts with a 2xx response. Extensions and subprotocols are
+// negotiated with the same headers as over HTTP/1.1, while `Sec-WebSocket-Key` and
+// `Sec-WebSocket-Accept` aren't used.
+//
+// After the handshake, the data frames of the stream in both directions carry the WebSocket
+// connection. Wrapping the stream in a type implementing `AsyncRead` and `AsyncWrite` and passing
+// it to `WsConnection::with_config` works as for HTTP/1.1: clients still mask frames and the
+// closing handshake is unchanged. Closing the transport should end the stream (END_STREAM),
+// while resetting the stream corresponds to a TCP reset.
+use crate::http::{check_negotiation, check_version, select_protocol, HandshakeError};
+use http::request::Builder;
+use http::{Method, Request, Response, StatusCode, Version};
+
+// The `:protocol` pseudo-header of an extended CONNECT request, carried in the request
+// extensions. HTTP/2 implementations expose the pseudo-header in their own types, which have to be
+// converted from and into this one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConnectProtocol(String);
+
+impl ConnectProtocol {
+    pub const WEBSOCKET: &'static str = "websocket";
+
+    pub fn new(protocol: &str) -> Self {
+        ConnectProtocol(protocol.to_string())
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for ConnectProtocol {
+    fn from(protocol: &str) -> Self {
+        ConnectProtocol::new(protocol)
+    }
+}
+
+// An HTTP/2 extended CONNECT request for a WebSocket. The `:scheme`, `:authority` and `:path`
+// pseudo-headers are taken from the URI, which has to be set on the builder.
+pub fn connect_request() -> Builder {
+    Request::builder()
+        .method(Method::CONNECT)
+        .version(Version::HTTP_2)
+        .extension(ConnectProtocol::new(ConnectProtocol::WEBSOCKET))
+        .header("Sec-WebSocket-Version", "13")
+}
+
+// Checks an extended CONNECT request for a WebSocket. The request has to be an HTTP/2 request,
+// since extended CONNECT is only defined for HTTP/2 (RFC 8441 section 4), with the `:protocol`
+// pseudo-header set to `websocket` and `Sec-WebSocket-Version: 13`.
+pub fn is_connect_request<T>(request: &Request<T>) -> Result<(), HandshakeError> {
+    if request.method() != Method::CONNECT {
+        return Err(HandshakeError::Method(request.method().clone()));
+    }
+    if request.version() != Version::HTTP_2 {
+        return Err(HandshakeError::HttpVersion(request.version()));
+    }
+    match request.extensions().get::<ConnectProtocol>() {
+        Some(protocol) if protocol.as_str() == ConnectProtocol::WEBSOCKET => {}
+        protocol => {
+            let protocol = protocol.map(|protocol| protocol.as_str().to_string());
+            return Err(HandshakeError::ConnectProtocol(protocol));
+        }
+    }
+    check_version(request.headers())
+}
+
+pub fn connect_response<T>(request: &Request<T>) -> Result<Response<()>, HandshakeError> {
+    connect_response_with(request, |_| None)
+}
+
+// Like `connect_response`, selecting one of the offered subprotocols like `upgrade_response_with`.
+pub fn connect_response_with<T, F>(
+    request: &Request<T>,
+    select: F,
+) -> Result<Response<()>, HandshakeError>
+where
+    F: for<'a> FnOnce(&[&'a str]) -> Option<&'a str>,
+{
+    is_connect_request(request)?;
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .version(request.version())
+        .body(())
+        .unwrap();
+    select_protocol(request, &mut response, select);
+    Ok(response)
+}
+
+// Any 2xx status accepts the request.
+pub fn check_connect_response<T, U>(
+    request: &Request<T>,
+    response: &Response<U>,
+) -> Result<(), HandshakeError> {
+    is_connect_request(request)?;
+    if !response.status().is_success() {
+        return Err(HandshakeError::Status(response.status()));
+    }
+    check_negotiation(request, response)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{
+        check_connect_response, connect_request, connect_response, connect_response_with,
+        is_connect_request, response_protocol, upgrade_request, ConnectProtocol, HandshakeError,
+    };
+    use http::{Method, StatusCode, Version};
+
+    #[test]
+    fn extended_connect() {
+        let request = connect_request()
+            .uri("https://example.com/chat")
+            .header("Sec-WebSocket-Protocol", "v2.example, v1.example")
+            .body(())
+            .unwrap();
+        assert_eq!(request.version(), Version::HTTP_2);
+        assert_eq!(request.headers().get("Sec-WebSocket-Key"), None);
+        let response = connect_response_with(&request, |offered| offered.last().copied()).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_2);
+        assert_eq!(response_protocol(&response), Some("v1.example"));
+        check_connect_response(&request, &response).unwrap();
+        let mut rejected = response.clone();
+        *rejected.status_mut() = StatusCode::SWITCHING_PROTOCOLS;
+        assert_eq!(
+            check_connect_response(&request, &rejected),
+            Err(HandshakeError::Status(StatusCode::SWITCHING_PROTOCOLS))
+        );
+    }
+
+    #[test]
+    fn connect_request_errors() {
+        let mut request = connect_request().body(()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectProtocol::from("h2c"));
+        assert_eq!(
+            is_connect_request(&request),
+            Err(HandshakeError::ConnectProtocol(Some("h2c".to_string())))
+        );
+        request.extensions_mut().clear();
+        assert_eq!(
+            connect_response(&request).unwrap_err(),
+            HandshakeError::ConnectProtocol(None)
+        );
+        let request = upgrade_request().body(()).unwrap();
+        assert_eq!(
+            is_connect_request(&request),
+            Err(HandshakeError::Method(Method::GET))
+        );
+        for version in [Version::HTTP_11, Version::HTTP_3].iter() {
+            let request = connect_request().version(*version).body(()).unwrap();
+            assert_eq!(
+                is_connect_request(&request),
+                Err(HandshakeError::HttpVersion(*version))
+            );
+        }
+    }
+}
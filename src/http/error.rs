@@ -0,0 +1,80 @@
+use http::uri::InvalidUri;
+use http::{HeaderValue, Method, Request, Response, StatusCode, Version};
+
+// Why a handshake failed, with the value seen for the failing field. Missing headers are `None`.
+#[derive(thiserror::Error, Clone, Debug, Eq, PartialEq)]
+pub enum HandshakeError {
+    #[error("unexpected request method {0}")]
+    Method(Method),
+    #[error("unsupported http version {0:?}")]
+    HttpVersion(Version),
+    #[error("unexpected response status {0}")]
+    Status(StatusCode),
+    #[error("invalid connection header {0:?}")]
+    Connection(Option<HeaderValue>),
+    #[error("invalid upgrade header {0:?}")]
+    Upgrade(Option<HeaderValue>),
+    #[error("unsupported websocket version {0:?}")]
+    Version(Option<HeaderValue>),
+    #[error("unexpected connect protocol {0:?}")]
+    ConnectProtocol(Option<String>),
+    #[error("invalid sec-websocket-key header {0:?}")]
+    Key(Option<HeaderValue>),
+    #[error("sec-websocket-accept mismatch: expected {expected}, got {actual:?}")]
+    Accept {
+        expected: String,
+        actual: Option<HeaderValue>,
+    },
+    #[error("invalid sec-websocket-extensions headers {0:?}")]
+    Extensions(Vec<HeaderValue>),
+    #[error("invalid sec-websocket-protocol headers {0:?}")]
+    Protocol(Vec<HeaderValue>),
+    #[error("origin {0:?} not allowed")]
+    Origin(Option<HeaderValue>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum UrlError {
+    #[error("invalid url: {0}")]
+    Invalid(#[from] InvalidUri),
+    #[error("unsupported scheme {0:?}, expected ws or wss")]
+    Scheme(Option<String>),
+    #[error("missing host")]
+    MissingHost,
+}
+
+impl HandshakeError {
+    // The status of the response rejecting a request that failed with this error. Requests that
+    // aren't upgrade requests or ask for another version get `426 Upgrade Required`.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HandshakeError::Method(_) => StatusCode::METHOD_NOT_ALLOWED,
+            HandshakeError::Connection(_)
+            | HandshakeError::Upgrade(_)
+            | HandshakeError::Version(_) => StatusCode::UPGRADE_REQUIRED,
+            HandshakeError::Origin(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+    // The response rejecting `request`, which failed with this error. Besides the status, it
+    // tells the client how to retry: `Allow` for `405` and `Upgrade` as well as the supported
+    // `Sec-WebSocket-Version` for `426` (RFC 6455 section 4.4).
+    pub fn response<T>(&self, request: &Request<T>) -> Response<()> {
+        let status = self.status();
+        let mut response = Response::builder()
+            .status(status)
+            .version(request.version())
+            .header("Content-Length", "0");
+        match status {
+            StatusCode::METHOD_NOT_ALLOWED => response = response.header("Allow", "GET"),
+            StatusCode::UPGRADE_REQUIRED => {
+                response = response
+                    .header("Connection", "Upgrade")
+                    .header("Upgrade", "websocket")
+                    .header("Sec-WebSocket-Version", "13")
+            }
+            _ => {}
+        }
+        response.body(()).unwrap()
+    }
+}
@@ -0,0 +1,68 @@
+use http::{HeaderMap, HeaderValue};
+
+// Whether `s` is a token (RFC 9110 section 5.6.2).
+pub(crate) fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
+
+fn trim(s: &str) -> &str {
+    s.trim_matches(|c| c == ' ' || c == '\t')
+}
+
+// Splits `s` at `separator` outside of quoted strings and trims optional whitespace around the
+// parts. Returns `None` if a quoted string isn't terminated.
+pub(crate) fn split_quoted(s: &str, separator: u8) -> Option<Vec<&str>> {
+    let mut parts = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in s.bytes().enumerate() {
+        match (quoted, escaped, c) {
+            (true, true, _) => escaped = false,
+            (true, false, b'\\') => escaped = true,
+            (_, false, b'"') => quoted = !quoted,
+            (false, _, c) if c == separator => {
+                parts.push(trim(&s[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if quoted {
+        return None;
+    }
+    parts.push(trim(&s[start..]));
+    Some(parts)
+}
+
+// The elements of a comma-separated list header (RFC 9110 section 5.6.1) over all of its values,
+// in order. Empty elements are skipped. Returns `None` if a value isn't visible ASCII or has an
+// unterminated quoted string.
+pub(crate) fn list<'a>(headers: &'a HeaderMap, name: &str) -> Option<Vec<&'a str>> {
+    let mut elements = Vec::new();
+    for value in headers.get_all(name) {
+        let parts = split_quoted(value.to_str().ok()?, b',')?;
+        elements.extend(parts.into_iter().filter(|part| !part.is_empty()));
+    }
+    Some(elements)
+}
+
+// Whether the list header `name` has an element matching `token` case-insensitively.
+pub(crate) fn list_contains(headers: &HeaderMap, name: &str, token: &str) -> bool {
+    match list(headers, name) {
+        Some(elements) => elements.iter().any(|e| e.eq_ignore_ascii_case(token)),
+        None => false,
+    }
+}
+
+// The value of a header that has to occur exactly once. Otherwise, returns the first value if any.
+pub(crate) fn single<'a>(
+    headers: &'a HeaderMap,
+    name: &str,
+) -> Result<&'a HeaderValue, Option<&'a HeaderValue>> {
+    let mut values = headers.get_all(name).iter();
+    match (values.next(), values.next()) {
+        (Some(value), None) => Ok(value),
+        (first, _) => Err(first),
+    }
+}
@@ -0,0 +1,148 @@
+use crate::extension::WsExtensionOffer;
+use crate::http::{header, response_protocol};
+use http::{HeaderMap, Request, Response, Uri};
+use std::net::{IpAddr, SocketAddr};
+
+// What is known about a connection from its handshake, carried by the connection for handlers
+// that need it later, e.g. for routing or authorization.
+#[derive(Clone, Debug)]
+pub struct WsHandshakeInfo {
+    uri: Uri,
+    headers: HeaderMap,
+    protocol: Option<String>,
+    extensions: Vec<String>,
+}
+
+impl WsHandshakeInfo {
+    // Records the request and the negotiation result of `response`, which has to be accepted.
+    pub fn new<T, U>(request: &Request<T>, response: &Response<U>) -> Self {
+        let extensions = WsExtensionOffer::parse_headers(response.headers()).unwrap_or_default();
+        Self {
+            uri: request.uri().clone(),
+            headers: request.headers().clone(),
+            protocol: response_protocol(response).map(str::to_string),
+            extensions: extensions
+                .iter()
+                .map(|extension| extension.name().to_string())
+                .collect(),
+        }
+    }
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+    pub fn path(&self) -> &str {
+        self.uri.path()
+    }
+    pub fn query(&self) -> Option<&str> {
+        self.uri.query()
+    }
+    // The headers of the upgrade request.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+    // The negotiated subprotocol.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+    // The names of the negotiated extensions, in negotiated order.
+    pub fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+    // The client address reported by proxies, from the first `for` parameter of `Forwarded`
+    // (RFC 7239) or else the first `X-Forwarded-For` entry. Clients can send these headers as
+    // well, so they can only be trusted behind a proxy that replaces them.
+    pub fn forwarded_for(&self) -> Option<IpAddr> {
+        match header::list(&self.headers, "Forwarded") {
+            Some(elements) if !elements.is_empty() => {
+                let params = header::split_quoted(elements[0], b';')?;
+                let node = params.iter().find_map(|param| {
+                    let (name, value) = param.split_once('=')?;
+                    match name.trim().eq_ignore_ascii_case("for") {
+                        true => Some(value.trim().trim_matches('"')),
+                        false => None,
+                    }
+                })?;
+                parse_node(node)
+            }
+            _ => parse_node(header::list(&self.headers, "X-Forwarded-For")?.first()?),
+        }
+    }
+}
+
+// Parses an address with an optional port. IPv6 addresses with a port are in brackets.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    let node = node
+        .strip_prefix('[')
+        .and_then(|node| node.strip_suffix(']'))
+        .unwrap_or(node);
+    node.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{
+        upgrade_request, upgrade_request_with_protocols, upgrade_response_with, WsHandshakeInfo,
+    };
+    use std::net::IpAddr;
+
+    #[test]
+    fn handshake_info() {
+        let request = upgrade_request_with_protocols(&["chat"])
+            .uri("/rooms/1?user=2")
+            .header("Authorization", "Bearer token")
+            .body(())
+            .unwrap();
+        let mut response =
+            upgrade_response_with(&request, |offered| offered.first().copied()).unwrap();
+        response.headers_mut().insert(
+            "Sec-WebSocket-Extensions",
+            "permessage-deflate; server_no_context_takeover, x-custom"
+                .parse()
+                .unwrap(),
+        );
+        let info = WsHandshakeInfo::new(&request, &response);
+        assert_eq!(info.path(), "/rooms/1");
+        assert_eq!(info.query(), Some("user=2"));
+        assert_eq!(info.headers()["Authorization"], "Bearer token");
+        assert_eq!(info.protocol(), Some("chat"));
+        assert_eq!(info.extensions(), ["permessage-deflate", "x-custom"]);
+        assert_eq!(info.forwarded_for(), None);
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let forwarded_for = |headers: &[(&str, &str)]| {
+            let mut request = upgrade_request();
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let request = request.body(()).unwrap();
+            let response = upgrade_response_with(&request, |_| None).unwrap();
+            WsHandshakeInfo::new(&request, &response).forwarded_for()
+        };
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        assert_eq!(
+            forwarded_for(&[("Forwarded", "for=192.0.2.43, for=198.51.100.17")]),
+            ip("192.0.2.43")
+        );
+        assert_eq!(
+            forwarded_for(&[("Forwarded", "proto=https;For=\"[2001:db8:cafe::17]:4711\"")]),
+            ip("2001:db8:cafe::17")
+        );
+        assert_eq!(forwarded_for(&[("Forwarded", "for=unknown")]), None);
+        assert_eq!(
+            forwarded_for(&[("X-Forwarded-For", "203.0.113.195, 70.41.3.18")]),
+            ip("203.0.113.195")
+        );
+        assert_eq!(
+            forwarded_for(&[
+                ("X-Forwarded-For", "203.0.113.195"),
+                ("Forwarded", "for=192.0.2.43")
+            ]),
+            ip("192.0.2.43")
+        );
+    }
+}
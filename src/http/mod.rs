@@ -0,0 +1,746 @@
+use crate::extension::{WsExtension, WsExtensionOffer};
+use http::request::Builder;
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version};
+use rand::{thread_rng, Rng};
+use ring::digest::{Context, SHA1_FOR_LEGACY_USE_ONLY};
+
+pub(crate) mod codec;
+mod connect;
+mod error;
+pub(crate) mod header;
+mod info;
+mod policy;
+mod response;
+
+pub use crate::http::connect::{
+    check_connect_response, connect_request, connect_response, connect_response_with,
+    is_connect_request, ConnectProtocol,
+};
+pub use crate::http::error::{HandshakeError, UrlError};
+pub use crate::http::info::WsHandshakeInfo;
+pub use crate::http::policy::HandshakePolicy;
+pub use crate::http::response::{UpgradeResponseBuilder, UpgradeResponseError};
+
+pub fn upgrade_request() -> Builder {
+    let mut nonce = [0u8; 16];
+    thread_rng().fill(&mut nonce);
+    Request::builder()
+        .method(Method::GET)
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", base64::encode(nonce))
+}
+
+// Where to connect to for a `ws://` or `wss://` URL. `host` is a domain name or IP address without
+// brackets, suitable for resolving together with `port`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WsTarget {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+}
+
+// Like `upgrade_request`, with the request target and `Host` header taken from a `ws://` or
+// `wss://` URL. Default ports are omitted from the `Host` header.
+pub fn upgrade_request_for(url: &str) -> Result<(Builder, WsTarget), UrlError> {
+    let uri: Uri = url.parse()?;
+    let tls = match uri.scheme_str() {
+        Some(scheme) if scheme.eq_ignore_ascii_case("ws") => false,
+        Some(scheme) if scheme.eq_ignore_ascii_case("wss") => true,
+        scheme => return Err(UrlError::Scheme(scheme.map(str::to_string))),
+    };
+    let host = uri
+        .host()
+        .filter(|host| !host.is_empty())
+        .ok_or(UrlError::MissingHost)?;
+    let default_port = match tls {
+        true => 443,
+        false => 80,
+    };
+    let port = uri.port_u16().unwrap_or(default_port);
+    let host_header = match port == default_port {
+        true => host.to_string(),
+        false => format!("{}:{}", host, port),
+    };
+    let target = uri.path_and_query().map_or("/", |target| target.as_str());
+    let builder = upgrade_request().uri(target).header("Host", host_header);
+    let target = WsTarget {
+        tls,
+        host: host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        port,
+    };
+    Ok((builder, target))
+}
+
+// Like `upgrade_request`, offering `protocols` in `Sec-WebSocket-Protocol` in order of preference.
+// The header is left out if `protocols` is empty.
+pub fn upgrade_request_with_protocols(protocols: &[&str]) -> Builder {
+    match protocols.is_empty() {
+        true => upgrade_request(),
+        false => upgrade_request().header("Sec-WebSocket-Protocol", protocols.join(", ")),
+    }
+}
+
+pub fn is_upgrade_request<T>(request: &Request<T>) -> Result<(), HandshakeError> {
+    request_challenge(request).map(drop)
+}
+
+// Checks an upgrade request and returns its `Sec-WebSocket-Key`.
+fn request_challenge<T>(request: &Request<T>) -> Result<&[u8], HandshakeError> {
+    let headers = request.headers();
+    if request.method() != Method::GET {
+        return Err(HandshakeError::Method(request.method().clone()));
+    }
+    // Upgrades need HTTP/1.1 (RFC 6455 section 4.1).
+    if request.version() < Version::HTTP_11 {
+        return Err(HandshakeError::HttpVersion(request.version()));
+    }
+    check_connection_header(headers)?;
+    check_upgrade_header(headers)?;
+    check_version(headers)?;
+    // The key is a base64 encoded 16 byte nonce (RFC 6455 section 4.2.1).
+    match header::single(headers, "Sec-WebSocket-Key") {
+        Ok(key) if matches!(base64::decode(key.as_bytes()), Ok(nonce) if nonce.len() == 16) => {
+            Ok(key.as_bytes())
+        }
+        Ok(key) | Err(Some(key)) => Err(HandshakeError::Key(Some(key.clone()))),
+        Err(None) => Err(HandshakeError::Key(None)),
+    }
+}
+
+fn check_version(headers: &HeaderMap) -> Result<(), HandshakeError> {
+    match header::single(headers, "Sec-WebSocket-Version") {
+        Ok(version) if version == "13" => Ok(()),
+        Ok(version) | Err(Some(version)) => Err(HandshakeError::Version(Some(version.clone()))),
+        Err(None) => Err(HandshakeError::Version(None)),
+    }
+}
+
+// `Connection` and `Upgrade` are lists, which may contain other elements as well.
+fn check_connection_header(headers: &HeaderMap) -> Result<(), HandshakeError> {
+    match header::list_contains(headers, "Connection", "Upgrade") {
+        true => Ok(()),
+        false => Err(HandshakeError::Connection(
+            headers.get("Connection").cloned(),
+        )),
+    }
+}
+
+fn check_upgrade_header(headers: &HeaderMap) -> Result<(), HandshakeError> {
+    match header::list_contains(headers, "Upgrade", "websocket") {
+        true => Ok(()),
+        false => Err(HandshakeError::Upgrade(headers.get("Upgrade").cloned())),
+    }
+}
+
+pub fn upgrade_response<T>(request: &Request<T>) -> Result<Response<()>, HandshakeError> {
+    upgrade_response_with(request, |_| None)
+}
+
+// Like `upgrade_response`, selecting one of the subprotocols offered by the client. `select` is
+// called with the offered protocols in order of the client's preference. A protocol that wasn't
+// offered is ignored.
+pub fn upgrade_response_with<T, F>(
+    request: &Request<T>,
+    select: F,
+) -> Result<Response<()>, HandshakeError>
+where
+    F: for<'a> FnOnce(&[&'a str]) -> Option<&'a str>,
+{
+    let challenge = request_challenge(request)?;
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .version(request.version())
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header(
+            "Sec-WebSocket-Accept",
+            upgrade_challenge_response(challenge),
+        )
+        .body(())
+        .unwrap();
+    select_protocol(request, &mut response, select);
+    Ok(response)
+}
+
+fn select_protocol<T, F>(request: &Request<T>, response: &mut Response<()>, select: F)
+where
+    F: for<'a> FnOnce(&[&'a str]) -> Option<&'a str>,
+{
+    let offered = offered_protocols(request);
+    if let Some(protocol) = select(&offered).filter(|protocol| offered.contains(protocol)) {
+        let protocol = HeaderValue::from_str(protocol).unwrap();
+        response
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", protocol);
+    }
+}
+
+// The response rejecting a request that isn't a valid upgrade request, see
+// `HandshakeError::response`. Requests that pass `is_upgrade_request` get `400 Bad Request`.
+pub fn rejection_response<T>(request: &Request<T>) -> Response<()> {
+    match is_upgrade_request(request) {
+        Err(err) => err.response(request),
+        Ok(()) => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .version(request.version())
+            .header("Content-Length", "0")
+            .body(())
+            .unwrap(),
+    }
+}
+
+// Like `upgrade_response_with`, but checks the request against `policy` first. Rejected requests
+// get a response with the status of the error, which can be sent as is.
+pub fn upgrade_response_with_policy<T, F>(
+    request: &Request<T>,
+    policy: &HandshakePolicy,
+    select: F,
+) -> Result<Response<()>, Response<()>>
+where
+    F: for<'a> FnOnce(&[&'a str]) -> Option<&'a str>,
+{
+    policy
+        .check_origin(request)
+        .and_then(|()| upgrade_response_with(request, select))
+        .map_err(|err| err.response(request))
+}
+
+// The subprotocols offered in `Sec-WebSocket-Protocol`, in order of preference.
+pub fn offered_protocols<T>(request: &Request<T>) -> Vec<&str> {
+    protocols(request.headers()).unwrap_or_default()
+}
+
+// The subprotocol selected by the server, if any. Only meaningful for responses that passed
+// `check_upgrade_response`.
+pub fn response_protocol<T>(response: &Response<T>) -> Option<&str> {
+    protocols(response.headers())?.first().copied()
+}
+
+// Returns `None` if the header is malformed or has elements that aren't tokens.
+fn protocols(headers: &HeaderMap) -> Option<Vec<&str>> {
+    header::list(headers, "Sec-WebSocket-Protocol")
+        .filter(|protocols| protocols.iter().all(|protocol| header::is_token(protocol)))
+}
+
+pub fn check_upgrade_response<T, U>(
+    request: &Request<T>,
+    response: &Response<U>,
+) -> Result<(), HandshakeError> {
+    let challenge = request_challenge(request)?;
+    let headers = response.headers();
+    if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(HandshakeError::Status(response.status()));
+    }
+    check_connection_header(headers)?;
+    check_upgrade_header(headers)?;
+    let expected = upgrade_challenge_response(challenge);
+    match header::single(headers, "Sec-WebSocket-Accept") {
+        Ok(accept) if accept == expected.as_str() => {}
+        Ok(actual) | Err(Some(actual)) => {
+            return Err(HandshakeError::Accept {
+                expected,
+                actual: Some(actual.clone()),
+            })
+        }
+        Err(None) => {
+            return Err(HandshakeError::Accept {
+                expected,
+                actual: None,
+            })
+        }
+    }
+    check_negotiation(request, response)
+}
+
+// Checks the extensions and subprotocol accepted in `response`.
+fn check_negotiation<T, U>(
+    request: &Request<T>,
+    response: &Response<U>,
+) -> Result<(), HandshakeError> {
+    let headers = response.headers();
+    if !check_response_extensions(request, response) {
+        return Err(HandshakeError::Extensions(all_values(
+            headers,
+            "Sec-WebSocket-Extensions",
+        )));
+    }
+    if !check_response_protocol(request, response) {
+        return Err(HandshakeError::Protocol(all_values(
+            headers,
+            "Sec-WebSocket-Protocol",
+        )));
+    }
+    Ok(())
+}
+
+fn all_values(headers: &HeaderMap, name: &str) -> Vec<HeaderValue> {
+    headers.get_all(name).iter().cloned().collect()
+}
+
+// The `Sec-WebSocket-Extensions` value offering `extensions` in order of preference.
+pub fn extension_offer(extensions: &[Box<dyn WsExtension>]) -> HeaderValue {
+    let offer = extensions
+        .iter()
+        .map(|extension| extension.offer())
+        .collect::<Vec<_>>()
+        .join(", ");
+    HeaderValue::from_str(&offer).unwrap()
+}
+
+// Accepts the extensions offered in `request` that are in `supported`, in the order of the offer.
+// Offers are skipped if an extension of the same name was accepted before, if the extension claims
+// no RSV bits or if they are already claimed. Returns the extensions for `WsConfig::extensions` and the
+// `Sec-WebSocket-Extensions` value for the response, if any extension was accepted.
+pub fn negotiate_extensions<T>(
+    request: &Request<T>,
+    mut supported: Vec<Box<dyn WsExtension>>,
+) -> (Vec<Box<dyn WsExtension>>, Option<HeaderValue>) {
+    let mut accepted: Vec<Box<dyn WsExtension>> = Vec::new();
+    let mut elements = Vec::new();
+    let mut rsv = 0;
+    let offers = WsExtensionOffer::parse_headers(request.headers()).unwrap_or_default();
+    for offer in offers {
+        let i = match supported
+            .iter()
+            .position(|extension| extension.name() == offer.name())
+        {
+            Some(i) if supported[i].rsv() != 0 && supported[i].rsv() & rsv == 0 => i,
+            _ => continue,
+        };
+        if let Some(element) = supported[i].accept(&offer) {
+            let extension = supported.remove(i);
+            rsv |= extension.rsv();
+            accepted.push(extension);
+            elements.push(element);
+        }
+    }
+    let header = match elements.is_empty() {
+        true => None,
+        false => Some(HeaderValue::from_str(&elements.join(", ")).unwrap()),
+    };
+    (accepted, header)
+}
+
+// Configures the extensions accepted in `response` from those `offered`, in the order of the
+// response. Returns `None` if the response accepts an extension that wasn't offered or accepts it
+// twice, if an accepted extension claims no RSV bits or they overlap, or if an extension rejects
+// the response.
+pub fn response_extensions<T>(
+    response: &Response<T>,
+    mut offered: Vec<Box<dyn WsExtension>>,
+) -> Option<Vec<Box<dyn WsExtension>>> {
+    let mut accepted: Vec<Box<dyn WsExtension>> = Vec::new();
+    let mut rsv = 0;
+    for element in WsExtensionOffer::parse_headers(response.headers())? {
+        let i = offered
+            .iter()
+            .position(|extension| extension.name() == element.name())?;
+        let mut extension = offered.remove(i);
+        if extension.rsv() == 0 || extension.rsv() & rsv != 0 || !extension.configure(&element) {
+            return None;
+        }
+        rsv |= extension.rsv();
+        accepted.push(extension);
+    }
+    Some(accepted)
+}
+
+// The server may select at most one of the offered subprotocols.
+fn check_response_protocol<T, U>(request: &Request<T>, response: &Response<U>) -> bool {
+    match protocols(response.headers()).as_deref() {
+        Some([]) => true,
+        Some([protocol]) => offered_protocols(request).contains(protocol),
+        _ => false,
+    }
+}
+
+// The server may only accept extensions offered by the client, each at most once.
+fn check_response_extensions<T, U>(request: &Request<T>, response: &Response<U>) -> bool {
+    let (offers, accepted) = match (
+        WsExtensionOffer::parse_headers(request.headers()),
+        WsExtensionOffer::parse_headers(response.headers()),
+    ) {
+        (Some(offers), Some(accepted)) => (offers, accepted),
+        _ => return false,
+    };
+    accepted.iter().enumerate().all(|(i, extension)| {
+        offers.iter().any(|offer| offer.name() == extension.name())
+            && accepted[..i]
+                .iter()
+                .all(|other| other.name() != extension.name())
+    })
+}
+
+fn upgrade_challenge_response(challenge: &[u8]) -> String {
+    let mut ctx = Context::new(&SHA1_FOR_LEGACY_USE_ONLY);
+    ctx.update(challenge);
+    ctx.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11");
+    base64::encode(ctx.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extension::{DeflateExtension, DeflateParams, WsExtension};
+    use crate::http::{
+        check_upgrade_response, extension_offer, is_upgrade_request, negotiate_extensions,
+        offered_protocols, rejection_response, response_extensions, response_protocol,
+        upgrade_challenge_response, upgrade_request, upgrade_request_for,
+        upgrade_request_with_protocols, upgrade_response, upgrade_response_with, HandshakeError,
+        UrlError, WsTarget,
+    };
+    use http::{HeaderValue, Method, Request, StatusCode};
+
+    fn offer(extensions: &[&str]) -> Request<()> {
+        let mut request = upgrade_request();
+        for extension in extensions {
+            request = request.header("Sec-WebSocket-Extensions", *extension);
+        }
+        request.body(()).unwrap()
+    }
+
+    fn deflate(params: DeflateParams) -> Vec<Box<dyn WsExtension>> {
+        vec![Box::new(DeflateExtension::new(params))]
+    }
+
+    // The response header when negotiating deflate with `params` on the server side.
+    fn negotiate(extensions: &[&str], params: DeflateParams) -> Option<String> {
+        let (accepted, header) = negotiate_extensions(&offer(extensions), deflate(params));
+        assert_eq!(accepted.len(), header.is_some() as usize);
+        header.map(|header| header.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn challenge_response() {
+        assert_eq!(
+            upgrade_challenge_response(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn deflate_negotiation() {
+        let default = DeflateParams::default();
+        let both = DeflateParams {
+            server_no_context_takeover: true,
+            client_no_context_takeover: true,
+        };
+        assert_eq!(negotiate(&[], default), None);
+        assert_eq!(
+            negotiate(&["permessage-deflate"], default).as_deref(),
+            Some("permessage-deflate")
+        );
+        assert_eq!(
+            negotiate(&["permessage-deflate"], both).as_deref(),
+            Some("permessage-deflate; server_no_context_takeover; client_no_context_takeover")
+        );
+        assert_eq!(
+            negotiate(
+                &["permessage-deflate; client_max_window_bits; server_no_context_takeover"],
+                default
+            )
+            .as_deref(),
+            Some("permessage-deflate; server_no_context_takeover")
+        );
+        // Smaller server windows are not supported, so the second offer is accepted.
+        assert_eq!(
+            negotiate(
+                &[
+                    "permessage-deflate; server_max_window_bits=10",
+                    "permessage-deflate; client_no_context_takeover"
+                ],
+                default
+            )
+            .as_deref(),
+            Some("permessage-deflate; client_no_context_takeover")
+        );
+        for invalid in [
+            "permessage-deflate; server_max_window_bits=\"15\"; server_max_window_bits=15",
+            "permessage-deflate; client_max_window_bits=16",
+            "permessage-deflate; client_max_window_bits=09",
+            "permessage-deflate; server_no_context_takeover=1",
+            "permessage-deflate; unknown",
+            "x-webkit-deflate-frame",
+            "permessage-deflate;;",
+        ]
+        .iter()
+        {
+            assert_eq!(negotiate(&[invalid], default), None);
+        }
+    }
+
+    #[test]
+    fn deflate_response() {
+        let offered = deflate(DeflateParams::default());
+        let request = offer(&[extension_offer(&offered).to_str().unwrap()]);
+        let mut response = upgrade_response(&request).unwrap();
+        check_upgrade_response(&request, &response).unwrap();
+        let accepted = response_extensions(&response, offered).unwrap();
+        assert!(accepted.is_empty());
+        response.headers_mut().insert(
+            "Sec-WebSocket-Extensions",
+            "permessage-deflate; server_no_context_takeover"
+                .parse()
+                .unwrap(),
+        );
+        check_upgrade_response(&request, &response).unwrap();
+        let accepted = response_extensions(&response, deflate(DeflateParams::default())).unwrap();
+        assert_eq!(accepted.len(), 1);
+        // Not offered.
+        let mut not_offered = request.clone();
+        not_offered.headers_mut().remove("Sec-WebSocket-Extensions");
+        assert!(matches!(
+            check_upgrade_response(&not_offered, &response),
+            Err(HandshakeError::Extensions(_))
+        ));
+        assert!(response_extensions(&response, Vec::new()).is_none());
+        for invalid in [
+            "permessage-deflate; client_max_window_bits=10",
+            "permessage-deflate, permessage-deflate",
+            "x-unknown",
+        ]
+        .iter()
+        {
+            response
+                .headers_mut()
+                .insert("Sec-WebSocket-Extensions", invalid.parse().unwrap());
+            assert!(
+                response_extensions(&response, deflate(DeflateParams::default())).is_none(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn protocol_negotiation() {
+        let request = upgrade_request_with_protocols(&["v2.example", "v1.example"])
+            .header("Sec-WebSocket-Protocol", "graphql-transport-ws")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            offered_protocols(&request),
+            vec!["v2.example", "v1.example", "graphql-transport-ws"]
+        );
+        let response = upgrade_response_with(&request, |offered| {
+            offered.iter().copied().find(|p| p.starts_with("graphql"))
+        })
+        .unwrap();
+        assert_eq!(response_protocol(&response), Some("graphql-transport-ws"));
+        check_upgrade_response(&request, &response).unwrap();
+        // A protocol that wasn't offered is ignored.
+        let response = upgrade_response_with(&request, |_| Some("v3.example")).unwrap();
+        assert_eq!(response_protocol(&response), None);
+        check_upgrade_response(&request, &response).unwrap();
+        let response = upgrade_response(&request).unwrap();
+        assert_eq!(response_protocol(&response), None);
+    }
+
+    #[test]
+    fn no_protocols_offered() {
+        let request = upgrade_request_with_protocols(&[]).body(()).unwrap();
+        assert!(!request.headers().contains_key("Sec-WebSocket-Protocol"));
+        assert!(offered_protocols(&request).is_empty());
+        is_upgrade_request(&request).unwrap();
+    }
+
+    #[test]
+    fn protocol_response_check() {
+        let request = upgrade_request_with_protocols(&["v1.example"])
+            .body(())
+            .unwrap();
+        let mut response = upgrade_response(&request).unwrap();
+        for invalid in ["v2.example", "v1.example, v1.example"].iter() {
+            response
+                .headers_mut()
+                .insert("Sec-WebSocket-Protocol", invalid.parse().unwrap());
+            assert_eq!(
+                check_upgrade_response(&request, &response),
+                Err(HandshakeError::Protocol(vec![invalid.parse().unwrap()])),
+            );
+        }
+        // Nothing was offered.
+        let mut request = request;
+        request.headers_mut().remove("Sec-WebSocket-Protocol");
+        response
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", "v1.example".parse().unwrap());
+        assert!(matches!(
+            check_upgrade_response(&request, &response),
+            Err(HandshakeError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn request_errors() {
+        let request = |edit: fn(&mut Request<()>)| {
+            let mut request = upgrade_request().body(()).unwrap();
+            edit(&mut request);
+            is_upgrade_request(&request).map(|_| request)
+        };
+        assert!(request(|_| {}).is_ok());
+        assert_eq!(
+            request(|r| *r.method_mut() = Method::POST).unwrap_err(),
+            HandshakeError::Method(Method::POST)
+        );
+        assert_eq!(
+            request(|r| {
+                r.headers_mut()
+                    .insert("Connection", "keep-alive".parse().unwrap());
+            })
+            .unwrap_err(),
+            HandshakeError::Connection(Some(HeaderValue::from_static("keep-alive")))
+        );
+        assert_eq!(
+            request(|r| {
+                r.headers_mut().remove("Upgrade");
+            })
+            .unwrap_err(),
+            HandshakeError::Upgrade(None)
+        );
+        assert_eq!(
+            request(|r| {
+                r.headers_mut()
+                    .insert("Sec-WebSocket-Version", "8".parse().unwrap());
+            })
+            .unwrap_err(),
+            HandshakeError::Version(Some(HeaderValue::from_static("8")))
+        );
+        assert_eq!(
+            request(|r| {
+                r.headers_mut().remove("Sec-WebSocket-Key");
+            })
+            .unwrap_err(),
+            HandshakeError::Key(None)
+        );
+    }
+
+    #[test]
+    fn response_errors() {
+        let request = upgrade_request().body(()).unwrap();
+        let response = upgrade_response(&request).unwrap();
+        let check = |edit: fn(&mut http::Response<()>)| {
+            let mut response = response.clone();
+            edit(&mut response);
+            check_upgrade_response(&request, &response)
+        };
+        assert_eq!(check(|_| {}), Ok(()));
+        assert_eq!(
+            check(|r| *r.status_mut() = StatusCode::OK),
+            Err(HandshakeError::Status(StatusCode::OK))
+        );
+        assert_eq!(
+            check(|r| {
+                r.headers_mut().remove("Connection");
+            }),
+            Err(HandshakeError::Connection(None))
+        );
+        assert_eq!(
+            check(|r| {
+                r.headers_mut().insert("Upgrade", "h2c".parse().unwrap());
+            }),
+            Err(HandshakeError::Upgrade(Some(HeaderValue::from_static(
+                "h2c"
+            ))))
+        );
+        let expected = response.headers()["Sec-WebSocket-Accept"].to_str().unwrap();
+        assert_eq!(
+            check(|r| {
+                r.headers_mut()
+                    .insert("Sec-WebSocket-Accept", "invalid".parse().unwrap());
+            }),
+            Err(HandshakeError::Accept {
+                expected: expected.to_string(),
+                actual: Some(HeaderValue::from_static("invalid")),
+            })
+        );
+    }
+
+    #[test]
+    fn request_for_url() {
+        let request = |url| {
+            let (builder, target) = upgrade_request_for(url).unwrap();
+            let request = builder.body(()).unwrap();
+            let host = request.headers()["Host"].to_str().unwrap().to_string();
+            (request.uri().to_string(), host, target)
+        };
+        let target = |tls, host: &str, port| WsTarget {
+            tls,
+            host: host.to_string(),
+            port,
+        };
+        assert_eq!(
+            request("ws://example.com"),
+            (
+                "/".into(),
+                "example.com".into(),
+                target(false, "example.com", 80)
+            )
+        );
+        assert_eq!(
+            request("WSS://example.com:443/chat?room=1&user=2"),
+            (
+                "/chat?room=1&user=2".into(),
+                "example.com".into(),
+                target(true, "example.com", 443)
+            )
+        );
+        assert_eq!(
+            request("ws://example.com:443/"),
+            (
+                "/".into(),
+                "example.com:443".into(),
+                target(false, "example.com", 443)
+            )
+        );
+        assert_eq!(
+            request("wss://[::1]:8443/ws"),
+            ("/ws".into(), "[::1]:8443".into(), target(true, "::1", 8443))
+        );
+        assert!(matches!(
+            upgrade_request_for("https://example.com"),
+            Err(UrlError::Scheme(Some(_)))
+        ));
+        assert!(matches!(
+            upgrade_request_for("/chat"),
+            Err(UrlError::Scheme(None))
+        ));
+        assert!(matches!(
+            upgrade_request_for("ws://exa mple.com"),
+            Err(UrlError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn rejections() {
+        let rejection = |edit: fn(&mut Request<()>)| {
+            let mut request = upgrade_request().body(()).unwrap();
+            edit(&mut request);
+            rejection_response(&request)
+        };
+        let response = rejection(|r| {
+            r.headers_mut()
+                .insert("Sec-WebSocket-Version", "8".parse().unwrap());
+        });
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(response.headers()["Sec-WebSocket-Version"], "13");
+        assert_eq!(response.headers()["Upgrade"], "websocket");
+        let response = rejection(|r| {
+            r.headers_mut().remove("Upgrade");
+        });
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+        let response = rejection(|r| *r.method_mut() = Method::POST);
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()["Allow"], "GET");
+        let response = rejection(|r| {
+            r.headers_mut().remove("Sec-WebSocket-Key");
+        });
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers().get("Sec-WebSocket-Version"), None);
+        assert_eq!(rejection(|_| {}).status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::http::HandshakeError;
+use http::Request;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+type OriginPredicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+// Server side checks of upgrade requests beyond the WebSocket protocol itself. Browsers send
+// WebSocket requests to any site with the user's cookies, so servers relying on them have to
+// check the `Origin` header to prevent cross-site WebSocket hijacking. The limits only apply to
+// `server::accept`, which reads the request itself.
+#[derive(Clone)]
+pub struct HandshakePolicy {
+    any_origin: bool,
+    origins: Vec<String>,
+    predicates: Vec<OriginPredicate>,
+    missing_origin: bool,
+    pub(crate) max_head_len: usize,
+    pub(crate) max_headers: usize,
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl HandshakePolicy {
+    // Rejects all requests until origins are allowed.
+    pub fn new() -> Self {
+        Self {
+            any_origin: false,
+            origins: Vec::new(),
+            predicates: Vec::new(),
+            missing_origin: false,
+            max_head_len: 16 * 1024,
+            max_headers: 64,
+            timeout: Some(Duration::from_secs(10)),
+        }
+    }
+    // Accepts requests from any origin or without an origin.
+    pub fn allow_any_origin() -> Self {
+        Self {
+            any_origin: true,
+            missing_origin: true,
+            ..Self::new()
+        }
+    }
+    // Allows an origin like `https://example.com` or `https://example.com:8443`. A host starting
+    // with `*.` allows all subdomains, so `https://*.example.com` allows `https://a.example.com`
+    // but not `https://example.com`. Origins are compared case-insensitively.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.to_ascii_lowercase());
+        self
+    }
+    // Allows origins for which `predicate` returns true.
+    pub fn allow_origin_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.push(Arc::new(predicate));
+        self
+    }
+    // Whether to accept requests without an `Origin` header, which are sent by non-browser
+    // clients.
+    pub fn allow_missing_origin(mut self, allow: bool) -> Self {
+        self.missing_origin = allow;
+        self
+    }
+    // Maximum size of the request head, 16 KiB by default.
+    pub fn max_head_len(mut self, max_head_len: usize) -> Self {
+        self.max_head_len = max_head_len;
+        self
+    }
+    // Maximum number of request headers, 64 by default.
+    pub fn max_headers(mut self, max_headers: usize) -> Self {
+        self.max_headers = max_headers;
+        self
+    }
+    // Time allowed for reading the request and writing the response, 10 seconds by default.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    // Checks whether the `Origin` of `request` is allowed. Requests with several or non-ASCII
+    // origins are rejected.
+    pub fn check_origin<T>(&self, request: &Request<T>) -> Result<(), HandshakeError> {
+        let mut origins = request.headers().get_all("Origin").iter();
+        let allowed = match (origins.next(), origins.next()) {
+            (None, _) => self.missing_origin,
+            (Some(origin), None) => match origin.to_str() {
+                Ok(origin) => self.allows(origin.trim()),
+                Err(_) => false,
+            },
+            (Some(_), Some(_)) => false,
+        };
+        match allowed {
+            true => Ok(()),
+            false => Err(HandshakeError::Origin(
+                request.headers().get("Origin").cloned(),
+            )),
+        }
+    }
+    fn allows(&self, origin: &str) -> bool {
+        self.any_origin
+            || self
+                .origins
+                .iter()
+                .any(|pattern| origin_matches(pattern, origin))
+            || self.predicates.iter().any(|predicate| predicate(origin))
+    }
+}
+
+impl Default for HandshakePolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for HandshakePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HandshakePolicy")
+            .field("any_origin", &self.any_origin)
+            .field("origins", &self.origins)
+            .field("predicates", &self.predicates.len())
+            .field("missing_origin", &self.missing_origin)
+            .field("max_head_len", &self.max_head_len)
+            .field("max_headers", &self.max_headers)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let origin = origin.to_ascii_lowercase();
+    let (scheme, host) = match pattern.split_once("://") {
+        Some(parts) => parts,
+        None => return pattern == origin,
+    };
+    let suffix = match host.strip_prefix('*') {
+        Some(suffix) if suffix.starts_with('.') => suffix,
+        _ => return pattern == origin,
+    };
+    let subdomain = origin
+        .strip_prefix(scheme)
+        .and_then(|rest| rest.strip_prefix("://"))
+        .and_then(|rest| rest.strip_suffix(suffix));
+    match subdomain {
+        Some(subdomain) => {
+            !subdomain.is_empty()
+                && subdomain
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'.')
+                && !subdomain.starts_with('.')
+                && !subdomain.ends_with('.')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::http::{upgrade_response_with_policy, HandshakePolicy};
+    use http::{Request, StatusCode};
+
+    fn request(origin: Option<&str>) -> Request<()> {
+        let mut request = crate::http::upgrade_request();
+        if let Some(origin) = origin {
+            request = request.header("Origin", origin);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[test]
+    fn origins() {
+        let policy = HandshakePolicy::new()
+            .allow_origin("https://example.com")
+            .allow_origin("https://*.example.org:8443")
+            .allow_origin_if(|origin| origin.ends_with(".test"));
+        for allowed in [
+            "https://example.com",
+            "HTTPS://Example.com",
+            "https://a.example.org:8443",
+            "https://a.b.example.org:8443",
+            "http://localhost.test",
+        ]
+        .iter()
+        {
+            assert!(
+                policy.check_origin(&request(Some(allowed))).is_ok(),
+                "{}",
+                allowed
+            );
+        }
+        for rejected in [
+            "null",
+            "http://example.com",
+            "https://example.com:8443",
+            "https://evil-example.com",
+            "https://example.com.evil.com",
+            "https://example.org:8443",
+            "https://a.example.org",
+            "https://.example.org:8443",
+            "http://a.example.org:8443",
+            "https://a/.example.org:8443",
+        ]
+        .iter()
+        {
+            assert!(
+                policy.check_origin(&request(Some(rejected))).is_err(),
+                "{}",
+                rejected
+            );
+        }
+        assert!(policy.check_origin(&request(None)).is_err());
+        let default = HandshakePolicy::default();
+        assert!(default
+            .check_origin(&request(Some("https://example.com")))
+            .is_err());
+        assert!(default.check_origin(&request(None)).is_err());
+        assert!(policy
+            .allow_missing_origin(true)
+            .check_origin(&request(None))
+            .is_ok());
+    }
+
+    #[test]
+    fn any_origin() {
+        let policy = HandshakePolicy::allow_any_origin();
+        assert!(policy
+            .check_origin(&request(Some("https://example.com")))
+            .is_ok());
+        assert!(policy.check_origin(&request(None)).is_ok());
+        let request = crate::http::upgrade_request()
+            .header("Origin", "https://a.example.com")
+            .header("Origin", "https://b.example.com")
+            .body(())
+            .unwrap();
+        assert!(policy.check_origin(&request).is_err());
+    }
+
+    #[test]
+    fn policy_responses() {
+        let policy = HandshakePolicy::new().allow_origin("https://example.com");
+        let response =
+            upgrade_response_with_policy(&request(Some("https://example.com")), &policy, |_| None);
+        assert_eq!(response.unwrap().status(), StatusCode::SWITCHING_PROTOCOLS);
+        let response =
+            upgrade_response_with_policy(&request(Some("https://evil.com")), &policy, |_| None);
+        assert_eq!(response.unwrap_err().status(), StatusCode::FORBIDDEN);
+        let request = Request::get("/")
+            .header("Origin", "https://example.com")
+            .body(())
+            .unwrap();
+        let response = upgrade_response_with_policy(&request, &policy, |_| None);
+        assert_eq!(response.unwrap_err().status(), StatusCode::UPGRADE_REQUIRED);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::http::{offered_protocols, upgrade_response, HandshakeError};
+use http::header::{HeaderName, HeaderValue};
+use http::{Request, Response};
+use std::convert::TryInto;
+
+// Headers an upgrade response can't carry: hop-by-hop headers, which would break the upgrade when
+// changed or forwarded, `Content-Length`, which 1xx responses must not have, and the headers
+// managed by the builder.
+const FORBIDDEN_HEADERS: [&str; 12] = [
+    "connection",
+    "upgrade",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "content-length",
+    "sec-websocket-accept",
+    "sec-websocket-protocol",
+    "sec-websocket-extensions",
+    "sec-websocket-version",
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum UpgradeResponseError {
+    #[error("header {0} can't be set on upgrade responses")]
+    ForbiddenHeader(HeaderName),
+    #[error("invalid header: {0}")]
+    InvalidHeader(#[from] http::Error),
+    #[error("protocol {0:?} wasn't offered")]
+    Protocol(String),
+}
+
+// Builds an upgrade response from the `101 Switching Protocols` response of `upgrade_response`.
+// Like `http::response::Builder`, the first error is returned by `build`.
+#[derive(Debug)]
+pub struct UpgradeResponseBuilder {
+    response: Response<()>,
+    offered_protocols: Vec<String>,
+    error: Option<UpgradeResponseError>,
+}
+
+impl UpgradeResponseBuilder {
+    pub fn new<T>(request: &Request<T>) -> Result<Self, HandshakeError> {
+        Ok(Self {
+            response: upgrade_response(request)?,
+            offered_protocols: offered_protocols(request)
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            error: None,
+        })
+    }
+    // Appends a header. Hop-by-hop headers and headers managed by the builder are refused.
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: TryInto<HeaderName>,
+        <K as TryInto<HeaderName>>::Error: Into<http::Error>,
+        V: TryInto<HeaderValue>,
+        <V as TryInto<HeaderValue>>::Error: Into<http::Error>,
+    {
+        if self.error.is_some() {
+            return self;
+        }
+        let header = name
+            .try_into()
+            .map_err(Into::into)
+            .and_then(|name| Ok((name, value.try_into().map_err(Into::into)?)));
+        match header {
+            Ok((name, _)) if FORBIDDEN_HEADERS.contains(&name.as_str()) => {
+                self.error = Some(UpgradeResponseError::ForbiddenHeader(name))
+            }
+            Ok((name, value)) => {
+                self.response.headers_mut().append(name, value);
+            }
+            Err(err) => self.error = Some(UpgradeResponseError::InvalidHeader(err)),
+        }
+        self
+    }
+    // Selects one of the subprotocols offered by the client.
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+        match self.offered_protocols.iter().any(|p| p == protocol) {
+            true => {
+                let protocol = HeaderValue::from_str(protocol).unwrap();
+                self.response
+                    .headers_mut()
+                    .insert("Sec-WebSocket-Protocol", protocol);
+            }
+            false => self.error = Some(UpgradeResponseError::Protocol(protocol.to_string())),
+        }
+        self
+    }
+    // Sets the accepted extensions, as returned by `negotiate_extensions`.
+    pub fn extensions(mut self, header: Option<HeaderValue>) -> Self {
+        match header {
+            Some(header) => self
+                .response
+                .headers_mut()
+                .insert("Sec-WebSocket-Extensions", header),
+            None => self
+                .response
+                .headers_mut()
+                .remove("Sec-WebSocket-Extensions"),
+        };
+        self
+    }
+    pub fn build(self) -> Result<Response<()>, UpgradeResponseError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extension::{DeflateExtension, WsExtension};
+    use crate::http::{
+        check_upgrade_response, extension_offer, negotiate_extensions,
+        upgrade_request_with_protocols, UpgradeResponseBuilder, UpgradeResponseError,
+    };
+    use http::StatusCode;
+
+    #[test]
+    fn extra_headers() {
+        let offered: Vec<Box<dyn WsExtension>> = vec![Box::new(DeflateExtension::default())];
+        let request = upgrade_request_with_protocols(&["chat", "superchat"])
+            .header("Sec-WebSocket-Extensions", extension_offer(&offered))
+            .body(())
+            .unwrap();
+        let (_, extensions) =
+            negotiate_extensions(&request, vec![Box::new(DeflateExtension::default())]);
+        let response = UpgradeResponseBuilder::new(&request)
+            .unwrap()
+            .header("Set-Cookie", "session=1")
+            .header("Set-Cookie", "theme=dark")
+            .header("X-Request-Id", "42")
+            .protocol("superchat")
+            .extensions(extensions)
+            .build()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.headers().get_all("Set-Cookie").iter().count(), 2);
+        assert_eq!(response.headers()["X-Request-Id"], "42");
+        assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "superchat");
+        assert_eq!(
+            response.headers()["Sec-WebSocket-Extensions"],
+            "permessage-deflate"
+        );
+        check_upgrade_response(&request, &response).unwrap();
+    }
+
+    #[test]
+    fn refused_headers() {
+        let request = upgrade_request_with_protocols(&["chat"]).body(()).unwrap();
+        let builder = || UpgradeResponseBuilder::new(&request).unwrap();
+        for name in [
+            "Connection",
+            "Upgrade",
+            "Keep-Alive",
+            "Transfer-Encoding",
+            "Content-Length",
+            "Sec-WebSocket-Accept",
+        ]
+        .iter()
+        {
+            match builder().header(*name, "close").build() {
+                Err(UpgradeResponseError::ForbiddenHeader(header)) => {
+                    assert!(header.as_str().eq_ignore_ascii_case(name))
+                }
+                result => panic!("expected {} to be refused, got: {:?}", name, result),
+            }
+        }
+        assert!(matches!(
+            builder().header("X-Invalid", "a\nb").build(),
+            Err(UpgradeResponseError::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            builder().protocol("superchat").build(),
+            Err(UpgradeResponseError::Protocol(_))
+        ));
+        // The first error is kept.
+        assert!(matches!(
+            builder()
+                .header("Upgrade", "h2c")
+                .protocol("superchat")
+                .build(),
+            Err(UpgradeResponseError::ForbiddenHeader(_))
+        ));
+    }
+}
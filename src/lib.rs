@@ -1,4 +1,7 @@
+pub mod client;
 pub mod connection;
+pub mod extension;
 pub mod frame;
 pub mod http;
 pub mod message;
+pub mod server;
@@ -14,3 +14,24 @@ impl WsMessageKind {
         }
     }
 }
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl WsMessage {
+    pub fn kind(&self) -> WsMessageKind {
+        match self {
+            WsMessage::Text(_) => WsMessageKind::Text,
+            WsMessage::Binary(_) => WsMessageKind::Binary,
+        }
+    }
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            WsMessage::Text(text) => text.as_bytes(),
+            WsMessage::Binary(data) => data,
+        }
+    }
+}
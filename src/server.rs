@@ -0,0 +1,159 @@
+use crate::connection::{WsConfig, WsConnection};
+use crate::http::codec::{parse_request, read_head, write_response, HeadError};
+use crate::http::{
+    check_upgrade_response, negotiate_extensions, upgrade_response, HandshakeError,
+    HandshakePolicy, WsHandshakeInfo,
+};
+use futures::future::{select, Either};
+use futures::prelude::*;
+use http::{HeaderValue, Request, Response, StatusCode};
+use std::io;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AcceptError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("request head exceeds the configured limits")]
+    RequestTooLarge,
+    #[error("invalid request head: {0}")]
+    InvalidRequest(httparse::Error),
+    #[error("handshake failed: {0}")]
+    Handshake(#[from] HandshakeError),
+    #[error("request rejected with status {0}")]
+    Rejected(StatusCode),
+    #[error("callback left an invalid upgrade response: {0}")]
+    InvalidResponse(HandshakeError),
+    #[error("timeout")]
+    Timeout,
+}
+
+impl From<HeadError> for AcceptError {
+    fn from(err: HeadError) -> Self {
+        match err {
+            HeadError::Io(err) => AcceptError::Io(err),
+            HeadError::TooLarge | HeadError::Invalid(httparse::Error::TooManyHeaders) => {
+                AcceptError::RequestTooLarge
+            }
+            HeadError::Invalid(err) => AcceptError::InvalidRequest(err),
+        }
+    }
+}
+
+// Performs the server handshake over `transport` and returns the connection along with the
+// request. Requests failing the checks of `policy` are rejected with an error response. The
+// extensions in `config` are negotiated with the client and replaced by those accepted.
+// `callback` is called with the request and the upgrade response, which it may amend, e.g. by
+// selecting a subprotocol, or replace with a rejection. An amended response has to pass
+// `check_upgrade_response` and keep the negotiated `Sec-WebSocket-Extensions`, otherwise the
+// request is answered with `500 Internal Server Error`.
+pub async fn accept<T, F>(
+    transport: T,
+    config: WsConfig,
+    policy: &HandshakePolicy,
+    callback: F,
+) -> Result<(WsConnection<T>, Request<()>), AcceptError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(&Request<()>, &mut Response<()>) -> Result<(), Response<()>>,
+{
+    let timeout = policy.timeout.map(|timeout| config.clock.sleep(timeout));
+    let handshake = Box::pin(handshake(transport, config, policy, callback));
+    match timeout {
+        None => handshake.await,
+        Some(timeout) => match select(handshake, timeout).await {
+            Either::Left((result, _)) => result,
+            Either::Right(((), _)) => Err(AcceptError::Timeout),
+        },
+    }
+}
+
+async fn handshake<T, F>(
+    mut transport: T,
+    mut config: WsConfig,
+    policy: &HandshakePolicy,
+    callback: F,
+) -> Result<(WsConnection<T>, Request<()>), AcceptError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+    F: FnOnce(&Request<()>, &mut Response<()>) -> Result<(), Response<()>>,
+{
+    let (request, buffered) = match read_request(&mut transport, policy).await {
+        Ok(request) => request,
+        Err(HeadError::Io(err)) => return Err(err.into()),
+        Err(err) => {
+            let err = AcceptError::from(err);
+            let status = match err {
+                AcceptError::RequestTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            let response = Response::builder()
+                .status(status)
+                .header("Content-Length", "0")
+                .body(())
+                .unwrap();
+            write_response(&mut transport, &response).await?;
+            return Err(err);
+        }
+    };
+    let mut response = match policy
+        .check_origin(&request)
+        .and_then(|()| upgrade_response(&request))
+    {
+        Ok(response) => response,
+        Err(err) => {
+            write_response(&mut transport, &err.response(&request)).await?;
+            return Err(err.into());
+        }
+    };
+    let supported = std::mem::take(&mut config.extensions);
+    let (extensions, header) = negotiate_extensions(&request, supported);
+    if let Some(header) = header {
+        response
+            .headers_mut()
+            .insert("Sec-WebSocket-Extensions", header);
+    }
+    config.extensions = extensions;
+    let negotiated = extension_headers(&response);
+    if let Err(rejection) = callback(&request, &mut response) {
+        write_response(&mut transport, &rejection).await?;
+        return Err(AcceptError::Rejected(rejection.status()));
+    }
+    let checked = match extension_headers(&response) == negotiated {
+        true => check_upgrade_response(&request, &response),
+        false => Err(HandshakeError::Extensions(extension_headers(&response))),
+    };
+    if let Err(err) = checked {
+        let response = Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .header("Content-Length", "0")
+            .body(())
+            .unwrap();
+        write_response(&mut transport, &response).await?;
+        return Err(AcceptError::InvalidResponse(err));
+    }
+    write_response(&mut transport, &response).await?;
+    let handshake = WsHandshakeInfo::new(&request, &response);
+    let connection =
+        WsConnection::with_buffered(transport, config, buffered).with_handshake(handshake);
+    Ok((connection, request))
+}
+
+fn extension_headers(response: &Response<()>) -> Vec<HeaderValue> {
+    response
+        .headers()
+        .get_all("Sec-WebSocket-Extensions")
+        .iter()
+        .cloned()
+        .collect()
+}
+
+// Reads the request head, returning the request and the bytes read after it.
+async fn read_request<T: AsyncRead + Unpin>(
+    transport: &mut T,
+    policy: &HandshakePolicy,
+) -> Result<(Request<()>, Vec<u8>), HeadError> {
+    let (mut buffer, len) = read_head(transport, policy.max_head_len).await?;
+    let request = parse_request(&buffer[..len], policy.max_headers)?;
+    buffer.drain(..len);
+    Ok((request, buffer))
+}
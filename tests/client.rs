@@ -0,0 +1,161 @@
+use crate::common::block_on;
+use crate::common::start_server_and_client_transport;
+use async_http_codec::RequestHead;
+use async_web_server::tcp::TcpStream;
+use async_ws::client::{connect, ConnectError, MAX_RESPONSE_HEAD_LEN};
+use async_ws::connection::{WsConfig, WsConnection};
+use async_ws::extension::{DeflateExtension, WsExtension};
+use async_ws::frame::{FrameHead, WsFrame, WsOpcode};
+use async_ws::http::{negotiate_extensions, upgrade_request_for, upgrade_response, HandshakeError};
+use async_ws::message::WsMessage;
+use futures::future::join;
+use futures::prelude::*;
+use http::{Request, Response, StatusCode};
+use smol_timeout::TimeoutExt;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+fn request() -> Request<()> {
+    let (request, _) = upgrade_request_for("ws://localhost/chat?room=1").unwrap();
+    request.body(()).unwrap()
+}
+
+fn encode_response(response: &Response<()>) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {}\r\n", response.status()).into_bytes();
+    for (name, value) in response.headers() {
+        head.extend_from_slice(format!("{}: ", name).as_bytes());
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    head
+}
+
+// Reads the request and writes `respond(request)` followed by `after` in a single write.
+async fn serve(
+    transport: TcpStream,
+    respond: impl FnOnce(&Request<()>) -> Vec<u8>,
+    after: &[u8],
+) -> (TcpStream, Request<()>) {
+    let (mut transport, head) = RequestHead::decode(transport).await.unwrap();
+    let request = Request::from(head);
+    let mut bytes = respond(&request);
+    bytes.extend_from_slice(after);
+    transport.write_all(&bytes).await.unwrap();
+    (transport, request)
+}
+
+#[test]
+fn handshake_with_trailing_frame() {
+    block_on(async {
+        let (server, client) = start_server_and_client_transport().await;
+        let hello = WsFrame::encode_vec(
+            FrameHead {
+                fin: true,
+                rsv1: false,
+                rsv2: false,
+                rsv3: false,
+                opcode: WsOpcode::Text,
+                mask: [0; 4],
+                payload_len: 5,
+            },
+            b"hello",
+        );
+        let respond = |request: &Request<()>| {
+            let supported: Vec<Box<dyn WsExtension>> = vec![Box::new(DeflateExtension::default())];
+            let (_, header) = negotiate_extensions(request, supported);
+            let mut response = upgrade_response(request).unwrap();
+            response
+                .headers_mut()
+                .insert("Sec-WebSocket-Extensions", header.unwrap());
+            encode_response(&response)
+        };
+        let mut config = WsConfig::client();
+        config.extensions = vec![Box::new(DeflateExtension::default())];
+        let ((server, request), connected) = join(
+            serve(server, respond, &hello),
+            connect(client, request(), config),
+        )
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        assert_eq!(request.uri().path_and_query().unwrap(), "/chat?room=1");
+        assert_eq!(request.headers()["Host"], "localhost");
+        assert_eq!(
+            request.headers()["Sec-WebSocket-Extensions"],
+            "permessage-deflate"
+        );
+        let (mut client, response) = connected.unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        let message = client.recv().timeout(ONE_S).await.unwrap();
+        assert_eq!(
+            message.unwrap().unwrap(),
+            WsMessage::Text("hello".to_string())
+        );
+        // The server side negotiated deflate, so compressed messages from the client are fine.
+        let mut server = WsConnection::with_config(server, {
+            let mut config = WsConfig::server();
+            config.extensions =
+                negotiate_extensions(&request, vec![Box::new(DeflateExtension::default())]).0;
+            config
+        });
+        let (sent, received) = join(client.send_text("hi"), server.recv())
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        assert_eq!(
+            received.unwrap().unwrap(),
+            WsMessage::Text("hi".to_string())
+        );
+    })
+}
+
+#[test]
+fn rejected_handshake() {
+    block_on(async {
+        let (server, client) = start_server_and_client_transport().await;
+        let respond =
+            |_: &Request<()>| b"HTTP/1.1 403 Forbidden\r\ncontent-length: 0\r\n\r\n".to_vec();
+        let (_, connected) = join(
+            serve(server, respond, &[]),
+            connect(client, request(), WsConfig::client()),
+        )
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        match connected {
+            Err(ConnectError::Handshake(HandshakeError::Status(StatusCode::FORBIDDEN))) => {}
+            result => panic!("expected status error, got: {:?}", result.map(|(_, r)| r)),
+        }
+    })
+}
+
+#[test]
+fn response_too_large() {
+    block_on(async {
+        let (server, client) = start_server_and_client_transport().await;
+        let respond = |request: &Request<()>| {
+            let mut response = upgrade_response(request).unwrap();
+            let padding = "x".repeat(MAX_RESPONSE_HEAD_LEN);
+            response
+                .headers_mut()
+                .insert("X-Padding", padding.parse().unwrap());
+            encode_response(&response)
+        };
+        let (_, connected) = join(
+            serve(server, respond, &[]),
+            connect(client, request(), WsConfig::client()),
+        )
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        match connected {
+            Err(ConnectError::ResponseTooLarge) => {}
+            result => panic!("expected size error, got: {:?}", result.map(|(_, r)| r)),
+        }
+    })
+}
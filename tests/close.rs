@@ -0,0 +1,239 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_and_client_transport;
+use crate::common::{next_control_frame, start_server_and_client_transport};
+use async_io::Timer;
+use async_ws::connection::{
+    WsCloseInitiator, WsCloseStatus, WsConfig, WsConnection, WsConnectionError,
+};
+use async_ws::frame::{FrameHead, WsControlFrame, WsControlFrameKind, WsFrame, WsOpcode};
+use async_ws::message::WsMessageKind;
+use futures::future::join;
+use futures::prelude::*;
+use futures::task::AtomicWaker;
+use smol_timeout::TimeoutExt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+#[test]
+fn client_initiated_close() {
+    block_on(async {
+        let (mut server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let (server_next, client_close) = join(server.next(), client.close(1001, "going away"))
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        assert!(server_next.is_none());
+        assert!(server.err().is_none());
+        let remote = WsCloseStatus {
+            code: Some(1001),
+            reason: "going away".to_string(),
+            initiated_by: WsCloseInitiator::Remote,
+        };
+        assert_eq!(server.close_status(), Some(remote));
+        let local = WsCloseStatus {
+            code: Some(1001),
+            reason: "going away".to_string(),
+            initiated_by: WsCloseInitiator::Local,
+        };
+        assert_eq!(client_close.unwrap(), local);
+        assert_eq!(client.close_status(), Some(local));
+    })
+}
+
+#[test]
+fn close_refuses_new_writers() {
+    block_on(async {
+        let (mut server, client) = start_server_ws_and_client_transport(None).await;
+        let mut client = WsConnection::with_config(client, WsConfig::client());
+        let (_, client_close) = join(server.next(), client.close(1000, ""))
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        assert_eq!(client_close.unwrap().code, Some(1000));
+        assert!(client.send(WsMessageKind::Text).await.is_none());
+        assert!(client.next().await.is_none());
+    })
+}
+
+#[test]
+fn close_rejects_invalid_code() {
+    block_on(async {
+        let (mut server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let err = client.close(1005, "").await.unwrap_err();
+        match &*err {
+            WsConnectionError::Io(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidInput),
+            err => panic!("unexpected error {:?}", err),
+        }
+        assert!(client.err().is_none());
+        let mut writer = client.send(WsMessageKind::Text).await.unwrap();
+        writer.write_all(b"still open").await.unwrap();
+        writer.close().await.unwrap();
+        drop(writer);
+        let message = server
+            .recv()
+            .timeout(ONE_S)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.as_bytes(), b"still open");
+    })
+}
+
+#[test]
+fn close_discards_incoming_messages() {
+    block_on(async {
+        let (server, client) = start_server_ws_and_client_transport(None).await;
+        let mut client = WsConnection::with_config(client, WsConfig::client());
+        let mut writer = server.send(WsMessageKind::Binary).await.unwrap();
+        writer.write_all(&[1, 2, 3]).await.unwrap();
+        writer.close().await.unwrap();
+        drop(writer);
+        let (client_close, server_close) =
+            join(client.close(1000, "done"), server.close(1000, "done"))
+                .timeout(ONE_S)
+                .await
+                .unwrap();
+        assert_eq!(client_close.unwrap().initiated_by, WsCloseInitiator::Local);
+        assert_eq!(server_close.unwrap().initiated_by, WsCloseInitiator::Local);
+        assert!(client.next().await.is_none());
+    })
+}
+
+// A transport whose flushes stay pending until released, to hold the encoder right after it wrote
+// a frame.
+struct HeldFlush<T> {
+    inner: T,
+    released: Arc<AtomicBool>,
+    waker: Arc<AtomicWaker>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for HeldFlush<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for HeldFlush<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.waker.register(cx.waker());
+        match self.released.load(Ordering::SeqCst) {
+            true => Pin::new(&mut self.inner).poll_flush(cx),
+            false => Poll::Pending,
+        }
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[test]
+fn nothing_sent_after_close() {
+    block_on(async {
+        let (server, mut client) = start_server_and_client_transport().await;
+        let released = Arc::new(AtomicBool::new(false));
+        let waker = Arc::new(AtomicWaker::new());
+        let server = HeldFlush {
+            inner: server,
+            released: released.clone(),
+            waker: waker.clone(),
+        };
+        let server = WsConnection::with_config(server, WsConfig::server());
+        let peer = async {
+            let close = next_control_frame(&mut client).await;
+            assert_eq!(close.kind(), WsControlFrameKind::Close);
+            // The ping arrives while the close frame is still being flushed.
+            let ping = WsControlFrame::new(WsControlFrameKind::Ping, b"late");
+            client
+                .write_all(&WsFrame::encode_vec(
+                    ping.head([1, 2, 3, 4]),
+                    ping.payload(),
+                ))
+                .await
+                .unwrap();
+            Timer::after(Duration::from_millis(50)).await;
+            released.store(true, Ordering::SeqCst);
+            waker.wake();
+            client
+                .write_all(&WsFrame::encode_vec(
+                    close.head([1, 2, 3, 4]),
+                    close.payload(),
+                ))
+                .await
+                .unwrap();
+        };
+        let (closed, ()) = join(server.close(1000, ""), peer)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        assert_eq!(closed.unwrap().code, Some(1000));
+        let mut buf = [0u8; 16];
+        let read = client
+            .read(&mut buf)
+            .timeout(Duration::from_millis(50))
+            .await;
+        assert!(
+            matches!(read, None | Some(Ok(0))),
+            "unexpected data after close: {:?}",
+            read
+        );
+    })
+}
+
+#[test]
+fn malformed_close_body() {
+    // A one byte body, codes 1005, 1015 and 5000, and a reason that isn't utf8.
+    let bodies: [(&[u8], u16); 5] = [
+        (&[3], 1002),
+        (&[3, 237], 1002),
+        (&[3, 247, b'x'], 1002),
+        (&[19, 136], 1002),
+        (&[3, 232, 0xff], 1007),
+    ];
+    for (body, code) in bodies.iter() {
+        block_on(async {
+            let (mut server, mut client) = start_server_ws_and_client_transport(None).await;
+            let head = FrameHead {
+                fin: true,
+                rsv1: false,
+                rsv2: false,
+                rsv3: false,
+                opcode: WsOpcode::Close,
+                mask: [1, 2, 3, 4],
+                payload_len: body.len() as u64,
+            };
+            client
+                .write_all(&WsFrame::encode_vec(head, body))
+                .await
+                .unwrap();
+            assert!(server.next().timeout(ONE_S).await.unwrap().is_none());
+            assert!(server.close_status().is_none());
+            let err = server.err().unwrap();
+            assert_eq!(err.close_code(), Some(*code), "{:?}", body);
+            let close = next_control_frame(&mut client).await;
+            assert_eq!(close.kind(), WsControlFrameKind::Close);
+            assert_eq!(close.payload()[..2], code.to_be_bytes());
+        })
+    }
+}
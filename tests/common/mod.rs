@@ -1,18 +1,37 @@
+use async_io::Timer;
 use async_web_server::tcp::{TcpIncoming, TcpStream};
 use async_ws::connection::{WsConfig, WsConnection};
+use async_ws::frame::{FrameDecoderState, WsControlFrame, WsControlFrameKind, WsFrame};
 use futures::future::join;
 use futures::prelude::*;
+use smol_timeout::TimeoutExt;
 use std::net::Ipv4Addr;
 use std::time::Duration;
+
+// Runs a test on the futures executor. Without the async-io feature, the default clock is the
+// `TokioClock`, which needs a tokio runtime with a running time driver.
+#[cfg(not(all(feature = "tokio", not(feature = "async-io"))))]
+#[allow(dead_code)]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    futures::executor::block_on(future)
+}
+
+#[cfg(all(feature = "tokio", not(feature = "async-io")))]
+#[allow(dead_code)]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let _guard = runtime.enter();
+    futures::executor::block_on(future)
+}
+
 async fn start_server_ws(
     mut tcp_incoming: TcpIncoming,
-    timeout: Option<Duration>,
+    config: WsConfig,
 ) -> WsConnection<TcpStream> {
     let tcp_stream = tcp_incoming.next().await.unwrap();
-    let mut config = WsConfig::server();
-    if let Some(timeout) = timeout {
-        config.timeout = timeout;
-    }
     WsConnection::with_config(tcp_stream, config)
 }
 
@@ -22,14 +41,81 @@ async fn start_client_transport(port: u16) -> TcpStream {
         .unwrap()
 }
 
+#[allow(dead_code)]
 pub async fn start_server_ws_and_client_transport(
     server_timeout: Option<Duration>,
+) -> (WsConnection<TcpStream>, TcpStream) {
+    let mut config = WsConfig::server();
+    if let Some(timeout) = server_timeout {
+        config.ping_interval = Some(timeout);
+        config.pong_timeout = timeout;
+    }
+    start_server_ws_with_config_and_client_transport(config).await
+}
+
+#[allow(dead_code)]
+pub async fn start_server_ws_with_config_and_client_transport(
+    server_config: WsConfig,
 ) -> (WsConnection<TcpStream>, TcpStream) {
     let tcp_incoming = TcpIncoming::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
     let port = tcp_incoming.local_addr().unwrap().port();
     join(
-        start_server_ws(tcp_incoming, server_timeout),
+        start_server_ws(tcp_incoming, server_config),
         start_client_transport(port),
     )
     .await
 }
+
+#[allow(dead_code)]
+pub async fn start_server_and_client_transport() -> (TcpStream, TcpStream) {
+    let mut tcp_incoming = TcpIncoming::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = tcp_incoming.local_addr().unwrap().port();
+    let (server, client) = join(tcp_incoming.next(), start_client_transport(port)).await;
+    (server.unwrap(), client)
+}
+
+// Waits for the next frame, failing if it doesn't arrive within a second of real time.
+#[allow(dead_code)]
+pub async fn next_control_frame<T: AsyncRead + Unpin>(transport: T) -> WsControlFrame {
+    let frame = FrameDecoderState::new()
+        .restore(transport)
+        .timeout(Duration::from_secs(1))
+        .await
+        .expect("no frame arrived")
+        .unwrap()
+        .1;
+    match frame {
+        WsFrame::Control(frame) => frame,
+        WsFrame::Data(_) => panic!("unexpected data frame"),
+    }
+}
+
+// Checks that no frame arrives. Since the timers only fire when the virtual clock is advanced,
+// waiting for a short period of real time can't cause spurious failures.
+#[allow(dead_code)]
+pub async fn no_frame<T: AsyncRead + Unpin>(transport: T) {
+    let frame = FrameDecoderState::new()
+        .restore(transport)
+        .timeout(Duration::from_millis(10))
+        .await
+        .map(|frame| frame.map(|(_, frame)| frame));
+    assert!(frame.is_none(), "unexpected frame: {:?}", frame);
+}
+
+#[allow(dead_code)]
+pub async fn write_pong<T: AsyncWrite + Unpin>(mut transport: T, payload: &[u8]) {
+    let pong = WsControlFrame::new(WsControlFrameKind::Pong, payload);
+    let pong_buffer = WsFrame::encode_vec(pong.head([1, 1, 1, 1]), pong.payload());
+    transport.write_all(&pong_buffer).await.unwrap();
+}
+
+#[allow(dead_code)]
+pub async fn wait_until(mut condition: impl FnMut() -> bool) {
+    for _ in 0..1000 {
+        if condition() {
+            return;
+        }
+        Timer::after(Duration::from_millis(1)).await;
+    }
+    panic!("condition not met in time")
+}
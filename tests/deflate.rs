@@ -0,0 +1,250 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_with_config_and_client_transport;
+use async_web_server::tcp::TcpStream;
+use async_ws::connection::{WsConfig, WsConnection, WsConnectionError};
+use async_ws::extension::{DeflateExtension, DeflateParams, WsExtension};
+use async_ws::frame::{FrameDecoderState, FrameHead, WsControlFrameKind, WsFrame, WsOpcode};
+use async_ws::http::upgrade_response;
+use async_ws::http::{extension_offer, negotiate_extensions, response_extensions, upgrade_request};
+use async_ws::message::{WsMessage, WsMessageKind};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use futures::future::join;
+use futures::prelude::*;
+use smol_timeout::TimeoutExt;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+type Extensions = Vec<Box<dyn WsExtension>>;
+
+fn text() -> String {
+    (0..20_000)
+        .map(|n| format!("message {} ", n % 100))
+        .collect()
+}
+
+// Negotiates deflate like a handshake would, returning the server and client extensions.
+fn negotiate(params: DeflateParams) -> (Extensions, Extensions) {
+    let offered: Extensions = vec![Box::new(DeflateExtension::new(params))];
+    let request = upgrade_request()
+        .header("Sec-WebSocket-Extensions", extension_offer(&offered))
+        .body(())
+        .unwrap();
+    let (server, header) =
+        negotiate_extensions(&request, vec![Box::new(DeflateExtension::default())]);
+    let mut response = upgrade_response(&request).unwrap();
+    response
+        .headers_mut()
+        .insert("Sec-WebSocket-Extensions", header.unwrap());
+    let client = response_extensions(&response, offered).unwrap();
+    (server, client)
+}
+
+fn server_config() -> WsConfig {
+    let mut config = WsConfig::server();
+    config.extensions = negotiate(DeflateParams::default()).0;
+    config
+}
+
+// Compresses a message like a peer would, including the stripped sync flush trailer.
+fn compress(payload: &[u8]) -> Vec<u8> {
+    let mut compress = Compress::new(Compression::default(), false);
+    let mut output = Vec::with_capacity(payload.len() + 64);
+    compress
+        .compress_vec(payload, &mut output, FlushCompress::Sync)
+        .unwrap();
+    assert!(output.ends_with(&[0, 0, 0xff, 0xff]));
+    output.truncate(output.len() - 4);
+    output
+}
+
+async fn write_compressed_frame(client: &mut TcpStream, opcode: WsOpcode, payload: &[u8]) {
+    let head = FrameHead {
+        fin: true,
+        rsv1: true,
+        rsv2: false,
+        rsv3: false,
+        opcode,
+        mask: [1, 2, 3, 4],
+        payload_len: payload.len() as u64,
+    };
+    client
+        .write_all(&WsFrame::encode_vec(head, payload))
+        .await
+        .unwrap();
+}
+
+async fn expect_failure(server: &mut WsConnection<TcpStream>, client: &mut TcpStream, code: u16) {
+    let drain = async {
+        while let Some(mut reader) = server.next().await {
+            assert!(reader.read_to_end(&mut Vec::new()).await.is_err());
+        }
+    };
+    drain.timeout(ONE_S).await.unwrap();
+    assert_eq!(server.err().unwrap().close_code(), Some(code));
+    let frame = FrameDecoderState::new().restore(client).await.unwrap().1;
+    match frame {
+        WsFrame::Control(frame) => {
+            assert_eq!(frame.kind(), WsControlFrameKind::Close);
+            assert_eq!(frame.payload()[0..2], code.to_be_bytes());
+        }
+        WsFrame::Data(_) => panic!("unexpected data frame"),
+    }
+}
+
+#[test]
+fn compressed_round_trip() {
+    for (server_no_context_takeover, client_no_context_takeover) in
+        [(false, false), (true, false), (false, true), (true, true)].iter()
+    {
+        block_on(async {
+            let params = DeflateParams {
+                server_no_context_takeover: *server_no_context_takeover,
+                client_no_context_takeover: *client_no_context_takeover,
+            };
+            let (server_extensions, client_extensions) = negotiate(params);
+            let mut server_config = WsConfig::server();
+            server_config.extensions = server_extensions;
+            let (mut server, client) =
+                start_server_ws_with_config_and_client_transport(server_config).await;
+            let mut client_config = WsConfig::client();
+            client_config.extensions = client_extensions;
+            let mut client = WsConnection::with_config(client, client_config);
+            let client_send = async {
+                for _ in 0..3 {
+                    let mut writer = client.send(WsMessageKind::Text).await.unwrap();
+                    for chunk in text().as_bytes().chunks(7000) {
+                        writer.write_all(chunk).await.unwrap();
+                        writer.flush().await.unwrap();
+                    }
+                    writer.close().await.unwrap();
+                }
+                client.send_binary(&[]).await.unwrap();
+            };
+            let server_echo = async {
+                for _ in 0..4 {
+                    match server.recv().await.unwrap().unwrap() {
+                        WsMessage::Text(text) => server.send_text(&text).await.unwrap(),
+                        WsMessage::Binary(data) => server.send_binary(&data).await.unwrap(),
+                    }
+                }
+            };
+            join(client_send, server_echo).timeout(ONE_S).await.unwrap();
+            for _ in 0..3 {
+                let echoed = client.recv().timeout(ONE_S).await.unwrap();
+                assert_eq!(echoed.unwrap().unwrap(), WsMessage::Text(text()));
+            }
+            let echoed = client.recv().timeout(ONE_S).await.unwrap();
+            assert_eq!(echoed.unwrap().unwrap(), WsMessage::Binary(Vec::new()));
+        })
+    }
+}
+
+#[test]
+fn compressed_frames() {
+    block_on(async {
+        let (server, mut client) =
+            start_server_ws_with_config_and_client_transport(server_config()).await;
+        let receive = async {
+            let mut decompress = Decompress::new(false);
+            let mut received = Vec::with_capacity(text().len() + 1);
+            let mut first = true;
+            loop {
+                let frame = match FrameDecoderState::new().restore(&mut client).await {
+                    Ok((_, WsFrame::Data(frame))) => frame,
+                    frame => panic!("unexpected frame: {:?}", frame),
+                };
+                assert_eq!(frame.rsv1(), first);
+                first = false;
+                let mut payload = Vec::new();
+                frame
+                    .payload_reader()
+                    .restore(&mut client)
+                    .read_to_end(&mut payload)
+                    .await
+                    .unwrap();
+                if frame.fin() {
+                    payload.extend_from_slice(&[0, 0, 0xff, 0xff]);
+                }
+                decompress
+                    .decompress_vec(&payload, &mut received, FlushDecompress::Sync)
+                    .unwrap();
+                if frame.fin() {
+                    return (decompress.total_in(), received);
+                }
+            }
+        };
+        let (sent, (compressed_len, received)) = join(server.send_text(&text()), receive)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        assert!(compressed_len < text().len() as u64 / 10);
+        assert_eq!(received, text().as_bytes());
+    })
+}
+
+#[test]
+fn decompressed_size_limit() {
+    block_on(async {
+        let (mut server, mut client) = {
+            let mut config = server_config();
+            config.max_message_size = 100_000;
+            start_server_ws_with_config_and_client_transport(config).await
+        };
+        let bomb = compress(&[0u8; 1_000_000]);
+        assert!(bomb.len() < 10_000);
+        write_compressed_frame(&mut client, WsOpcode::Binary, &bomb).await;
+        expect_failure(&mut server, &mut client, 1009).await;
+        match server.err().as_deref() {
+            Some(WsConnectionError::MessageTooBig) => {}
+            err => panic!("expected message too big error, got: {:?}", err),
+        }
+    })
+}
+
+#[test]
+fn invalid_compressed_data() {
+    block_on(async {
+        let (mut server, mut client) =
+            start_server_ws_with_config_and_client_transport(server_config()).await;
+        write_compressed_frame(&mut client, WsOpcode::Binary, &[0xff; 16]).await;
+        expect_failure(&mut server, &mut client, 1007).await;
+    })
+}
+
+// Peers may end a message with a final block instead of a sync flush, which ends the deflate
+// stream even with context takeover.
+#[test]
+fn final_block_ends_message() {
+    block_on(async {
+        let (mut server, mut client) =
+            start_server_ws_with_config_and_client_transport(server_config()).await;
+        let mut compress_final = Compress::new(Compression::default(), false);
+        let mut hello = Vec::with_capacity(64);
+        compress_final
+            .compress_vec(b"hello", &mut hello, FlushCompress::Finish)
+            .unwrap();
+        write_compressed_frame(&mut client, WsOpcode::Text, &hello).await;
+        write_compressed_frame(&mut client, WsOpcode::Text, &compress(b"world")).await;
+        for expected in ["hello", "world"].iter() {
+            let message = server.recv().timeout(ONE_S).await.unwrap();
+            assert_eq!(
+                message.unwrap().unwrap(),
+                WsMessage::Text(expected.to_string())
+            );
+        }
+    })
+}
+
+#[test]
+fn rsv1_without_deflate() {
+    block_on(async {
+        let (mut server, mut client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        write_compressed_frame(&mut client, WsOpcode::Text, &compress(b"hello")).await;
+        expect_failure(&mut server, &mut client, 1002).await;
+    })
+}
@@ -0,0 +1,380 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_with_config_and_client_transport;
+use async_ws::connection::{WsConfig, WsConnection, WsConnectionError};
+use async_ws::extension::{
+    DeflateExtension, WsExtension, WsExtensionDecoder, WsExtensionEncoder, WsExtensionOffer,
+    WsFlush, RSV1, RSV2,
+};
+use async_ws::frame::{FrameDecoderState, FrameHead, WsControlFrameKind, WsFrame, WsOpcode};
+use async_ws::http::{
+    extension_offer, negotiate_extensions, response_extensions, upgrade_request, upgrade_response,
+};
+use async_ws::message::WsMessage;
+use futures::future::join;
+use futures::prelude::*;
+use smol_timeout::TimeoutExt;
+use std::io;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+type Extensions = Vec<Box<dyn WsExtension>>;
+
+// Xors the payload of every message with a key, claiming the given RSV bits.
+struct XorExtension {
+    name: &'static str,
+    rsv: u8,
+    key: u8,
+}
+
+impl XorExtension {
+    fn boxed(name: &'static str, rsv: u8) -> Box<dyn WsExtension> {
+        Box::new(XorExtension { name, rsv, key: 0 })
+    }
+}
+
+impl WsExtension for XorExtension {
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn rsv(&self) -> u8 {
+        self.rsv
+    }
+    fn offer(&self) -> String {
+        format!("{}; key=85", self.name)
+    }
+    fn accept(&mut self, offer: &WsExtensionOffer) -> Option<String> {
+        self.configure(offer)
+            .then(|| format!("{}; key={}", self.name, self.key))
+    }
+    fn configure(&mut self, response: &WsExtensionOffer) -> bool {
+        match response.params() {
+            [(name, Some(key))] if name == "key" => match key.parse() {
+                Ok(key) => {
+                    self.key = key;
+                    true
+                }
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+    fn split(self: Box<Self>) -> (Box<dyn WsExtensionEncoder>, Box<dyn WsExtensionDecoder>) {
+        (Box::new(Xor(self.key)), Box::new(Xor(self.key)))
+    }
+}
+
+struct Xor(u8);
+
+impl WsExtensionEncoder for Xor {
+    // A zero key is refused, to test how encoder failures are handled.
+    fn encode(&mut self, input: &[u8], _flush: WsFlush, output: &mut Vec<u8>) -> io::Result<()> {
+        if self.0 == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "zero key"));
+        }
+        output.extend(input.iter().map(|b| b ^ self.0));
+        Ok(())
+    }
+}
+
+impl WsExtensionDecoder for Xor {
+    fn decode(
+        &mut self,
+        input: &[u8],
+        _end: bool,
+        output: &mut [u8],
+    ) -> Result<(usize, usize), WsConnectionError> {
+        let n = input.len().min(output.len());
+        for (output, input) in output.iter_mut().zip(&input[..n]) {
+            *output = input ^ self.0;
+        }
+        Ok((n, n))
+    }
+}
+
+// Passes outgoing payload through and discards incoming payload once the message ended.
+struct DiscardExtension;
+
+impl WsExtension for DiscardExtension {
+    fn name(&self) -> &str {
+        "x-discard"
+    }
+    fn rsv(&self) -> u8 {
+        RSV2
+    }
+    fn offer(&self) -> String {
+        self.name().to_string()
+    }
+    fn accept(&mut self, _offer: &WsExtensionOffer) -> Option<String> {
+        Some(self.offer())
+    }
+    fn configure(&mut self, _response: &WsExtensionOffer) -> bool {
+        true
+    }
+    fn split(self: Box<Self>) -> (Box<dyn WsExtensionEncoder>, Box<dyn WsExtensionDecoder>) {
+        (Box::new(DiscardExtension), Box::new(DiscardExtension))
+    }
+}
+
+impl WsExtensionEncoder for DiscardExtension {
+    fn encode(&mut self, input: &[u8], _flush: WsFlush, output: &mut Vec<u8>) -> io::Result<()> {
+        output.extend_from_slice(input);
+        Ok(())
+    }
+}
+
+impl WsExtensionDecoder for DiscardExtension {
+    fn decode(
+        &mut self,
+        input: &[u8],
+        end: bool,
+        _output: &mut [u8],
+    ) -> Result<(usize, usize), WsConnectionError> {
+        match end {
+            true => Ok((input.len(), 0)),
+            false => Ok((0, 0)),
+        }
+    }
+}
+
+fn supported() -> Extensions {
+    vec![
+        Box::new(DeflateExtension::default()),
+        XorExtension::boxed("x-xor", RSV2),
+        XorExtension::boxed("x-xor-rsv1", RSV1),
+    ]
+}
+
+// Negotiates `offered` against `supported()` and returns the server and client extensions along
+// with the response header.
+fn negotiate(offered: Extensions) -> (Extensions, Extensions, String) {
+    let request = upgrade_request()
+        .header("Sec-WebSocket-Extensions", extension_offer(&offered))
+        .body(())
+        .unwrap();
+    let (server, header) = negotiate_extensions(&request, supported());
+    let header = header.unwrap();
+    let mut response = upgrade_response(&request).unwrap();
+    response
+        .headers_mut()
+        .insert("Sec-WebSocket-Extensions", header.clone());
+    let client = response_extensions(&response, offered).unwrap();
+    (server, client, header.to_str().unwrap().to_string())
+}
+
+fn text() -> String {
+    (0..10_000).map(|n| format!("{} ", n % 10)).collect()
+}
+
+#[test]
+fn negotiation_order_and_rsv_conflicts() {
+    let (server, client, header) = negotiate(vec![
+        XorExtension::boxed("x-xor", RSV2),
+        XorExtension::boxed("x-xor-rsv1", RSV1),
+        Box::new(DeflateExtension::default()),
+    ]);
+    assert_eq!(header, "x-xor; key=85, x-xor-rsv1; key=85");
+    let names = |extensions: &[Box<dyn WsExtension>]| {
+        extensions
+            .iter()
+            .map(|e| e.name().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&server), vec!["x-xor", "x-xor-rsv1"]);
+    assert_eq!(names(&client), names(&server));
+    // Deflate claims RSV1 as well, so it can't be accepted along with x-xor-rsv1.
+    let (server, _, header) = negotiate(vec![
+        Box::new(DeflateExtension::default()),
+        XorExtension::boxed("x-xor-rsv1", RSV1),
+        XorExtension::boxed("x-xor", RSV2),
+    ]);
+    assert_eq!(header, "permessage-deflate, x-xor; key=85");
+    assert_eq!(names(&server), vec!["permessage-deflate", "x-xor"]);
+}
+
+#[test]
+fn chained_extensions() {
+    block_on(async {
+        let (server_extensions, client_extensions, _) = negotiate(vec![
+            Box::new(DeflateExtension::default()),
+            XorExtension::boxed("x-xor", RSV2),
+        ]);
+        let mut server_config = WsConfig::server();
+        server_config.extensions = server_extensions;
+        let (mut server, client) =
+            start_server_ws_with_config_and_client_transport(server_config).await;
+        let mut client_config = WsConfig::client();
+        client_config.extensions = client_extensions;
+        let mut client = WsConnection::with_config(client, client_config);
+        let echo = async {
+            let message = server.recv().await.unwrap().unwrap();
+            if let WsMessage::Text(text) = message {
+                server.send_text(&text).await.unwrap();
+            }
+        };
+        let (sent, _) = join(client.send_text(&text()), echo)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        let echoed = client.recv().timeout(ONE_S).await.unwrap();
+        assert_eq!(echoed.unwrap().unwrap(), WsMessage::Text(text()));
+    })
+}
+
+#[test]
+fn rsv_bits_on_first_frame() {
+    block_on(async {
+        let mut config = WsConfig::server();
+        config.extensions = negotiate(vec![XorExtension::boxed("x-xor", RSV2)]).0;
+        config.max_outgoing_frame_size = 1000;
+        let (server, mut client) = start_server_ws_with_config_and_client_transport(config).await;
+        let receive = async {
+            let mut frames = Vec::new();
+            let mut received = Vec::new();
+            loop {
+                let frame = match FrameDecoderState::new().restore(&mut client).await {
+                    Ok((_, WsFrame::Data(frame))) => frame,
+                    frame => panic!("unexpected frame: {:?}", frame),
+                };
+                frames.push(frame.rsv());
+                frame
+                    .payload_reader()
+                    .restore(&mut client)
+                    .read_to_end(&mut received)
+                    .await
+                    .unwrap();
+                if frame.fin() {
+                    return (frames, received);
+                }
+            }
+        };
+        let (sent, (frames, received)) = join(server.send_binary(&[1; 1500]), receive)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        assert_eq!(frames, vec![RSV2, 0]);
+        assert_eq!(received, vec![1 ^ 85; 1500]);
+    })
+}
+
+#[test]
+fn unclaimed_rsv_bits() {
+    block_on(async {
+        let mut config = WsConfig::server();
+        config.extensions = negotiate(vec![XorExtension::boxed("x-xor", RSV2)]).0;
+        let (mut server, mut client) =
+            start_server_ws_with_config_and_client_transport(config).await;
+        let head = FrameHead {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: true,
+            opcode: WsOpcode::Binary,
+            mask: [1, 2, 3, 4],
+            payload_len: 0,
+        };
+        client
+            .write_all(&WsFrame::encode_vec(head, &[]))
+            .await
+            .unwrap();
+        let drain = async { while server.next().await.is_some() {} };
+        drain.timeout(ONE_S).await.unwrap();
+        match server.err().as_deref() {
+            Some(WsConnectionError::UnexpectedRsv) => {}
+            err => panic!("expected unexpected rsv error, got: {:?}", err),
+        }
+        let frame = FrameDecoderState::new()
+            .restore(&mut client)
+            .await
+            .unwrap()
+            .1;
+        match frame {
+            WsFrame::Control(frame) => {
+                assert_eq!(frame.kind(), WsControlFrameKind::Close);
+                assert_eq!(frame.payload()[0..2], 1002u16.to_be_bytes());
+            }
+            WsFrame::Data(_) => panic!("unexpected data frame"),
+        }
+    })
+}
+
+#[test]
+fn encoder_error_fails_connection() {
+    block_on(async {
+        let mut config = WsConfig::server();
+        config.extensions = vec![XorExtension::boxed("x-xor", RSV2)];
+        let (server, _client) = start_server_ws_with_config_and_client_transport(config).await;
+        let sent = server.send_text("hello").timeout(ONE_S).await.unwrap();
+        assert!(sent.is_err());
+        match server.err().as_deref() {
+            Some(WsConnectionError::Io(err)) => assert_eq!(err.kind(), io::ErrorKind::InvalidInput),
+            err => panic!("expected io error, got: {:?}", err),
+        }
+    })
+}
+
+#[test]
+fn extensions_without_rsv_bits() {
+    let request = upgrade_request()
+        .header("Sec-WebSocket-Extensions", "x-xor; key=85")
+        .body(())
+        .unwrap();
+    let (accepted, header) = negotiate_extensions(&request, vec![XorExtension::boxed("x-xor", 0)]);
+    assert!(accepted.is_empty());
+    assert!(header.is_none());
+    let mut response = upgrade_response(&request).unwrap();
+    response
+        .headers_mut()
+        .insert("Sec-WebSocket-Extensions", "x-xor; key=85".parse().unwrap());
+    assert!(response_extensions(&response, vec![XorExtension::boxed("x-xor", 0)]).is_none());
+    block_on(async {
+        // The zero key would fail the connection if the extension was applied.
+        let mut config = WsConfig::server();
+        config.extensions = vec![XorExtension::boxed("x-xor", 0)];
+        let (server, client) = start_server_ws_with_config_and_client_transport(config).await;
+        let mut client = WsConnection::with_config(client, WsConfig::client());
+        let (sent, received) = join(server.send_text("hello"), client.recv())
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        assert_eq!(
+            received.unwrap().unwrap(),
+            WsMessage::Text("hello".to_string())
+        );
+    })
+}
+
+#[test]
+fn intermediate_stage_size_limit() {
+    block_on(async {
+        // Incoming messages are inflated first and buffered by the discarding stage until they end.
+        let extensions = || -> Extensions {
+            vec![
+                Box::new(DiscardExtension),
+                Box::new(DeflateExtension::default()),
+            ]
+        };
+        let mut config = WsConfig::server();
+        config.max_message_size = 100_000;
+        config.extensions = extensions();
+        let (mut server, client) = start_server_ws_with_config_and_client_transport(config).await;
+        let mut config = WsConfig::client();
+        config.extensions = extensions();
+        let client = WsConnection::with_config(client, config);
+        let receive = async { while server.next().await.is_some() {} };
+        let (sent, _) = join(client.send_binary(&[0; 1_000_000]), receive)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        match server.err().as_deref() {
+            Some(WsConnectionError::MessageTooBig) => {}
+            err => panic!("expected message too big error, got: {:?}", err),
+        }
+    })
+}
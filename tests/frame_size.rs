@@ -0,0 +1,249 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_with_config_and_client_transport;
+use async_ws::connection::{WsConfig, WsConnection};
+use async_ws::frame::{max_payload_len, FrameDecoderState, FrameHead, WsFrame, WsOpcode};
+use async_ws::message::{WsMessage, WsMessageKind};
+use futures::future::join;
+use futures::prelude::*;
+use smol_timeout::TimeoutExt;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+fn payload() -> Vec<u8> {
+    (0..100_000u32).map(|n| n as u8).collect()
+}
+
+#[test]
+fn outgoing_frame_size() {
+    block_on(async {
+        let mut config = WsConfig::server();
+        config.max_outgoing_frame_size = 70_000;
+        let (server, mut client) = start_server_ws_with_config_and_client_transport(config).await;
+        let receive = async {
+            let mut frame_sizes = Vec::new();
+            let mut received = Vec::new();
+            loop {
+                let frame = match FrameDecoderState::new().restore(&mut client).await {
+                    Ok((_, WsFrame::Data(frame))) => frame,
+                    frame => panic!("unexpected frame: {:?}", frame),
+                };
+                let head_len = FrameHead {
+                    fin: frame.fin(),
+                    rsv1: frame.rsv1(),
+                    rsv2: frame.rsv2(),
+                    rsv3: frame.rsv3(),
+                    opcode: frame.kind().opcode(),
+                    mask: frame.mask(),
+                    payload_len: frame.payload_len(),
+                }
+                .len_bytes();
+                frame_sizes.push(head_len as u64 + frame.payload_len());
+                frame
+                    .payload_reader()
+                    .restore(&mut client)
+                    .read_to_end(&mut received)
+                    .await
+                    .unwrap();
+                if frame.fin() {
+                    return (frame_sizes, received);
+                }
+            }
+        };
+        let (sent, (frame_sizes, received)) = join(server.send_binary(&payload()), receive)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        assert_eq!(frame_sizes, vec![70_000, 100_000 - 70_000 + 10 + 4]);
+        assert_eq!(received, payload());
+    })
+}
+
+#[test]
+fn masked_unaligned_writes() {
+    block_on(async {
+        let (mut server, client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        let mut config = WsConfig::client();
+        config.max_outgoing_frame_size = 1001;
+        let client = WsConnection::with_config(client, config);
+        let send = async {
+            let mut writer = client.send(WsMessageKind::Binary).await.unwrap();
+            for chunk in payload().chunks(7) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.close().await.unwrap();
+        };
+        let (_, received) = join(send, server.recv()).timeout(ONE_S).await.unwrap();
+        assert_eq!(received.unwrap().unwrap(), WsMessage::Binary(payload()));
+    })
+}
+
+#[test]
+fn minimum_frame_size() {
+    block_on(async {
+        let (mut server, client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        let mut config = WsConfig::client();
+        config.max_outgoing_frame_size = 0;
+        let client = WsConnection::with_config(client, config);
+        let (sent, received) = join(client.send_text("hello"), server.recv())
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        assert_eq!(
+            received.unwrap().unwrap(),
+            WsMessage::Text("hello".to_string())
+        );
+    })
+}
+
+#[test]
+fn sized_message() {
+    block_on(async {
+        let (server, mut client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        let send = async {
+            let mut writer = server
+                .send_sized(WsMessageKind::Binary, payload().len() as u64)
+                .await
+                .unwrap();
+            writer.write_all(&payload()).await.unwrap();
+            writer.close().await.unwrap();
+        };
+        let receive = async {
+            let frame = match FrameDecoderState::new().restore(&mut client).await {
+                Ok((_, WsFrame::Data(frame))) => frame,
+                frame => panic!("unexpected frame: {:?}", frame),
+            };
+            let mut received = Vec::new();
+            frame
+                .payload_reader()
+                .restore(&mut client)
+                .read_to_end(&mut received)
+                .await
+                .unwrap();
+            (frame.fin(), received)
+        };
+        let (_, (fin, received)) = join(send, receive).timeout(ONE_S).await.unwrap();
+        assert!(fin);
+        assert_eq!(received, payload());
+    })
+}
+
+#[test]
+fn masked_sized_message() {
+    block_on(async {
+        let (mut server, client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let send = async {
+            let mut writer = client
+                .send_sized(WsMessageKind::Binary, payload().len() as u64)
+                .await
+                .unwrap();
+            for chunk in payload().chunks(7) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.close().await.unwrap();
+        };
+        let (_, received) = join(send, server.recv()).timeout(ONE_S).await.unwrap();
+        assert_eq!(received.unwrap().unwrap(), WsMessage::Binary(payload()));
+    })
+}
+
+#[test]
+fn masked_sized_message_large_writes() {
+    block_on(async {
+        let (mut server, client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        let mut config = WsConfig::client();
+        config.max_outgoing_frame_size = 8192;
+        let client = WsConnection::with_config(client, config);
+        let send = async {
+            let mut writer = client
+                .send_sized(WsMessageKind::Binary, payload().len() as u64)
+                .await
+                .unwrap();
+            let n = writer.write(&payload()[..5000]).await.unwrap();
+            assert!(n > 1300);
+            writer.write_all(&payload()[n..]).await.unwrap();
+            writer.close().await.unwrap();
+        };
+        let (_, received) = join(send, server.recv()).timeout(ONE_S).await.unwrap();
+        assert_eq!(received.unwrap().unwrap(), WsMessage::Binary(payload()));
+    })
+}
+
+#[test]
+fn sized_message_length_mismatch() {
+    block_on(async {
+        let (server, _client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        let mut writer = server.send_sized(WsMessageKind::Binary, 3).await.unwrap();
+        let err = writer.write_all(&[1, 2, 3, 4]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        writer.write_all(&[1, 2]).await.unwrap();
+        assert!(writer.close().await.is_err());
+        assert!(server.err().is_some());
+    })
+}
+
+#[test]
+fn sized_message_too_long() {
+    block_on(async {
+        let (mut server, client) =
+            start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let mut writer = client
+            .send_sized(WsMessageKind::Binary, 1 << 63)
+            .await
+            .unwrap();
+        let err = writer.write_all(&[1, 2, 3]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(
+            writer.close().await.unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        drop(writer);
+        assert!(client.err().is_none());
+        let (sent, received) = join(client.send_binary(&[1, 2, 3]), server.recv())
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        assert_eq!(received.unwrap().unwrap(), WsMessage::Binary(vec![1, 2, 3]));
+    })
+}
+
+#[test]
+fn max_payload_len_fits_frame_size() {
+    let frame_len = |mask: [u8; 4], payload_len: u64| {
+        let head = FrameHead {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode: WsOpcode::Binary,
+            mask,
+            payload_len,
+        };
+        head.len_bytes() as u64 + payload_len
+    };
+    for &mask in [[0u8; 4], [1u8, 2, 3, 4]].iter() {
+        let min_frame_size = frame_len(mask, 1);
+        for max_frame_size in (0..300).chain(65_400..65_700) {
+            let payload_len = max_payload_len(mask != [0u8; 4], max_frame_size);
+            if max_frame_size < min_frame_size {
+                assert_eq!(payload_len, 0);
+                continue;
+            }
+            assert!(frame_len(mask, payload_len) <= max_frame_size);
+            assert!(frame_len(mask, payload_len + 1) > max_frame_size);
+        }
+    }
+}
@@ -0,0 +1,410 @@
+use async_ws::http::{
+    check_upgrade_response, is_upgrade_request, offered_protocols, upgrade_response, HandshakeError,
+};
+use http::{HeaderValue, Method, Request, Response, StatusCode, Version};
+
+const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+type Headers<'a> = Vec<(&'a str, &'a str)>;
+type Expected = Result<(), HandshakeError>;
+
+fn request(method: Method, headers: &[(&str, &str)]) -> Request<()> {
+    let mut request = Request::builder().method(method).uri("/chat");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.body(()).unwrap()
+}
+
+fn response(status: u16, headers: &[(&str, &str)]) -> Response<()> {
+    let mut response = Response::builder().status(status);
+    for (name, value) in headers {
+        response = response.header(*name, *value);
+    }
+    response.body(()).unwrap()
+}
+
+fn value(value: &str) -> Option<HeaderValue> {
+    Some(HeaderValue::from_str(value).unwrap())
+}
+
+#[test]
+fn requests() {
+    let cases: Vec<(&str, Method, Version, Headers, Expected)> = vec![
+        (
+            "chrome",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Host", "example.com"),
+                ("Connection", "Upgrade"),
+                ("Pragma", "no-cache"),
+                ("Cache-Control", "no-cache"),
+                ("Upgrade", "websocket"),
+                ("Origin", "https://example.com"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+                (
+                    "Sec-WebSocket-Extensions",
+                    "permessage-deflate; client_max_window_bits",
+                ),
+            ],
+            Ok(()),
+        ),
+        (
+            "firefox",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Host", "example.com"),
+                ("Connection", "keep-alive, Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+                ("Sec-WebSocket-Extensions", "permessage-deflate"),
+            ],
+            Ok(()),
+        ),
+        (
+            "safari",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Host", "example.com"),
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+                (
+                    "Sec-WebSocket-Extensions",
+                    "permessage-deflate; client_max_window_bits=\"15\"",
+                ),
+            ],
+            Ok(()),
+        ),
+        (
+            "lower case values behind a proxy",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("connection", "upgrade"),
+                ("upgrade", "WebSocket"),
+                ("x-forwarded-for", "10.0.0.1"),
+                ("sec-websocket-version", "13"),
+                ("sec-websocket-key", KEY),
+            ],
+            Ok(()),
+        ),
+        (
+            "repeated connection headers from a proxy",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "keep-alive"),
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Ok(()),
+        ),
+        (
+            "upgrade token list",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket, h2c"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Ok(()),
+        ),
+        (
+            "space separated connection tokens",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "keep-alive Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Err(HandshakeError::Connection(value("keep-alive Upgrade"))),
+        ),
+        (
+            "proxy stripped hop-by-hop headers",
+            Method::GET,
+            Version::HTTP_11,
+            vec![("Sec-WebSocket-Version", "13"), ("Sec-WebSocket-Key", KEY)],
+            Err(HandshakeError::Connection(None)),
+        ),
+        (
+            "h2c upgrade",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade, HTTP2-Settings"),
+                ("Upgrade", "h2c"),
+                ("HTTP2-Settings", "AAMAAABkAARAAAAAAAIAAAAA"),
+            ],
+            Err(HandshakeError::Upgrade(value("h2c"))),
+        ),
+        (
+            "post",
+            Method::POST,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Err(HandshakeError::Method(Method::POST)),
+        ),
+        (
+            "hybi-08 client",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "8"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Err(HandshakeError::Version(value("8"))),
+        ),
+        (
+            "version list",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13, 8"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Err(HandshakeError::Version(value("13, 8"))),
+        ),
+        (
+            "repeated version",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Err(HandshakeError::Version(value("13"))),
+        ),
+        (
+            "short key",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", "c2hvcnQ="),
+            ],
+            Err(HandshakeError::Key(value("c2hvcnQ="))),
+        ),
+        (
+            "key not base64",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", "not a base64 key!!!!!!=="),
+            ],
+            Err(HandshakeError::Key(value("not a base64 key!!!!!!=="))),
+        ),
+        (
+            "repeated key",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Err(HandshakeError::Key(value(KEY))),
+        ),
+        (
+            "missing key",
+            Method::GET,
+            Version::HTTP_11,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+            ],
+            Err(HandshakeError::Key(None)),
+        ),
+        (
+            "http/1.0",
+            Method::GET,
+            Version::HTTP_10,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Version", "13"),
+                ("Sec-WebSocket-Key", KEY),
+            ],
+            Err(HandshakeError::HttpVersion(Version::HTTP_10)),
+        ),
+    ];
+    for (name, method, version, headers, expected) in cases {
+        let mut request = request(method, &headers);
+        *request.version_mut() = version;
+        assert_eq!(is_upgrade_request(&request), expected, "{}", name);
+        assert_eq!(upgrade_response(&request).map(drop), expected, "{}", name);
+    }
+}
+
+#[test]
+fn responses() {
+    let request = request(
+        Method::GET,
+        &[
+            ("Connection", "Upgrade"),
+            ("Upgrade", "websocket"),
+            ("Sec-WebSocket-Version", "13"),
+            ("Sec-WebSocket-Key", KEY),
+            ("Sec-WebSocket-Protocol", "chat, superchat"),
+            ("Sec-WebSocket-Extensions", "permessage-deflate"),
+        ],
+    );
+    assert_eq!(offered_protocols(&request), vec!["chat", "superchat"]);
+    let cases: Vec<(&str, u16, Headers, Expected)> = vec![
+        (
+            "rfc 6455 example",
+            101,
+            vec![
+                ("Upgrade", "websocket"),
+                ("Connection", "Upgrade"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+                ("Sec-WebSocket-Protocol", "chat"),
+            ],
+            Ok(()),
+        ),
+        (
+            "nginx with keep-alive",
+            101,
+            vec![
+                ("Server", "nginx"),
+                ("Connection", "upgrade, keep-alive"),
+                ("Upgrade", "WebSocket"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+            ],
+            Ok(()),
+        ),
+        (
+            "repeated connection headers",
+            101,
+            vec![
+                ("Connection", "keep-alive"),
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+                (
+                    "Sec-WebSocket-Extensions",
+                    "permessage-deflate; server_no_context_takeover",
+                ),
+            ],
+            Ok(()),
+        ),
+        (
+            "load balancer error page",
+            502,
+            vec![("Content-Type", "text/html")],
+            Err(HandshakeError::Status(StatusCode::BAD_GATEWAY)),
+        ),
+        (
+            "missing connection",
+            101,
+            vec![("Upgrade", "websocket"), ("Sec-WebSocket-Accept", ACCEPT)],
+            Err(HandshakeError::Connection(None)),
+        ),
+        (
+            "wrong upgrade",
+            101,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "h2c"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+            ],
+            Err(HandshakeError::Upgrade(value("h2c"))),
+        ),
+        (
+            "repeated accept",
+            101,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+                ("Sec-WebSocket-Accept", ACCEPT),
+            ],
+            Err(HandshakeError::Accept {
+                expected: ACCEPT.to_string(),
+                actual: value(ACCEPT),
+            }),
+        ),
+        (
+            "several protocols",
+            101,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+                ("Sec-WebSocket-Protocol", "chat"),
+                ("Sec-WebSocket-Protocol", "superchat"),
+            ],
+            Err(HandshakeError::Protocol(vec![
+                value("chat").unwrap(),
+                value("superchat").unwrap(),
+            ])),
+        ),
+        (
+            "malformed protocol",
+            101,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+                ("Sec-WebSocket-Protocol", "\"chat\""),
+            ],
+            Err(HandshakeError::Protocol(vec![value("\"chat\"").unwrap()])),
+        ),
+        (
+            "unterminated quoted extension parameter",
+            101,
+            vec![
+                ("Connection", "Upgrade"),
+                ("Upgrade", "websocket"),
+                ("Sec-WebSocket-Accept", ACCEPT),
+                ("Sec-WebSocket-Extensions", "permessage-deflate; x=\"15"),
+            ],
+            Err(HandshakeError::Extensions(vec![value(
+                "permessage-deflate; x=\"15",
+            )
+            .unwrap()])),
+        ),
+    ];
+    for (name, status, headers, expected) in cases {
+        let response = response(status, &headers);
+        assert_eq!(
+            check_upgrade_response(&request, &response),
+            expected,
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_with_config_and_client_transport;
+use async_web_server::tcp::TcpStream;
+use async_ws::connection::{WsConfig, WsConnection, WsConnectionError};
+use async_ws::frame::{FrameDecoderState, FrameHead, WsControlFrameKind, WsFrame, WsOpcode};
+use futures::prelude::*;
+use smol_timeout::TimeoutExt;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+async fn write_frame(client: &mut TcpStream, fin: bool, opcode: WsOpcode, payload: &[u8]) {
+    let head = FrameHead {
+        fin,
+        rsv1: false,
+        rsv2: false,
+        rsv3: false,
+        opcode,
+        mask: [1, 2, 3, 4],
+        payload_len: payload.len() as u64,
+    };
+    client
+        .write_all(&WsFrame::encode_vec(head, payload))
+        .await
+        .unwrap();
+}
+
+async fn expect_message_too_big(server: &mut WsConnection<TcpStream>, client: &mut TcpStream) {
+    let drain = async {
+        while let Some(mut reader) = server.next().await {
+            assert!(reader.read_to_end(&mut Vec::new()).await.is_err());
+        }
+    };
+    drain.timeout(ONE_S).await.unwrap();
+    match server.err().as_deref() {
+        Some(WsConnectionError::MessageTooBig) => {}
+        err => panic!("expected message too big error, got: {:?}", err),
+    }
+    let frame = FrameDecoderState::new().restore(client).await.unwrap().1;
+    match frame {
+        WsFrame::Control(frame) => {
+            assert_eq!(frame.kind(), WsControlFrameKind::Close);
+            assert_eq!(frame.payload()[0..2], 1009u16.to_be_bytes());
+        }
+        WsFrame::Data(_) => panic!("unexpected data frame"),
+    }
+}
+
+#[test]
+fn message_size_limit() {
+    block_on(async {
+        let (mut server, mut client) = {
+            let mut config = WsConfig::server();
+            config.max_message_size = 10;
+            start_server_ws_with_config_and_client_transport(config).await
+        };
+        write_frame(&mut client, false, WsOpcode::Binary, &[0; 6]).await;
+        write_frame(&mut client, true, WsOpcode::Continuation, &[0; 6]).await;
+        expect_message_too_big(&mut server, &mut client).await;
+    })
+}
+
+#[test]
+fn frame_payload_limit() {
+    block_on(async {
+        let (mut server, mut client) = {
+            let mut config = WsConfig::server();
+            config.max_frame_payload_len = 10;
+            start_server_ws_with_config_and_client_transport(config).await
+        };
+        write_frame(&mut client, true, WsOpcode::Binary, &[0; 11]).await;
+        expect_message_too_big(&mut server, &mut client).await;
+    })
+}
+
+#[test]
+fn fragment_limit() {
+    block_on(async {
+        let (mut server, mut client) = {
+            let mut config = WsConfig::server();
+            config.max_fragments = 3;
+            start_server_ws_with_config_and_client_transport(config).await
+        };
+        write_frame(&mut client, false, WsOpcode::Text, b"a").await;
+        write_frame(&mut client, false, WsOpcode::Continuation, b"b").await;
+        write_frame(&mut client, false, WsOpcode::Continuation, b"c").await;
+        write_frame(&mut client, true, WsOpcode::Continuation, b"d").await;
+        expect_message_too_big(&mut server, &mut client).await;
+    })
+}
@@ -0,0 +1,57 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_and_client_transport;
+use async_ws::connection::{WsConfig, WsConnection, WsConnectionError};
+use async_ws::message::WsMessage;
+use futures::future::join;
+use smol_timeout::TimeoutExt;
+use std::io;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+#[test]
+fn send_and_receive_messages() {
+    block_on(async {
+        let (mut server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let send = async {
+            client.send_text("hello").await.unwrap();
+            client.send_binary(&[1, 2, 3]).await.unwrap();
+        };
+        let receive = async {
+            let text = server.recv().await.unwrap().unwrap();
+            let binary = server.recv().await.unwrap().unwrap();
+            (text, binary)
+        };
+        let (_, (text, binary)) = join(send, receive).timeout(ONE_S).await.unwrap();
+        assert_eq!(text, WsMessage::Text("hello".to_string()));
+        assert_eq!(binary, WsMessage::Binary(vec![1, 2, 3]));
+    })
+}
+
+#[test]
+fn receive_message_too_long() {
+    block_on(async {
+        let (server, client) = start_server_ws_and_client_transport(None).await;
+        let mut config = WsConfig::client();
+        config.max_message_size = 4;
+        let mut client = WsConnection::with_config(client, config);
+        let send = async {
+            server.send_binary(&[0u8; 5]).await.unwrap();
+        };
+        let receive = async {
+            while let Some(received) = client.recv().await {
+                assert_eq!(received.unwrap_err().kind(), io::ErrorKind::InvalidData);
+            }
+        };
+        join(send, receive).timeout(ONE_S).await.unwrap();
+        let err = client.err().unwrap();
+        match *err {
+            WsConnectionError::MessageTooBig => {}
+            ref err => panic!("expected message too big error, got: {:?}", err),
+        }
+        assert_eq!(err.close_code(), Some(1009));
+    })
+}
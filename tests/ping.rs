@@ -0,0 +1,54 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_and_client_transport;
+use async_ws::connection::{WsConfig, WsConnection};
+use futures::future::{join, select, Either};
+use futures::prelude::*;
+use smol_timeout::TimeoutExt;
+use std::io;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+#[test]
+fn ping_round_trip_time() {
+    block_on(async {
+        let (mut server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        assert!(client.latency().is_none());
+        let pings = join(client.ping(b"a"), client.ping(b"b"));
+        let rtts = match select(pings, server.next()).timeout(ONE_S).await.unwrap() {
+            Either::Left(((a, b), _)) => (a.unwrap(), b.unwrap()),
+            Either::Right(_) => panic!("server stream ended"),
+        };
+        let latency = client.latency().unwrap();
+        assert!(latency <= rtts.0.max(rtts.1));
+        assert!(latency >= rtts.0.min(rtts.1));
+    })
+}
+
+#[test]
+fn ping_after_close() {
+    block_on(async {
+        let (mut server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let (_, client_close) = join(server.next(), client.close(1000, ""))
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        assert!(client_close.is_ok());
+        assert!(client.ping(b"").await.is_err());
+    })
+}
+
+#[test]
+fn ping_payload_too_long() {
+    block_on(async {
+        let (_server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let err = client.ping(&[0; 126]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(client.err().is_none());
+    })
+}
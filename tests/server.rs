@@ -0,0 +1,276 @@
+use crate::common::block_on;
+use crate::common::start_server_and_client_transport;
+use async_web_server::tcp::TcpStream;
+use async_ws::client::connect;
+use async_ws::connection::WsConfig;
+use async_ws::extension::DeflateExtension;
+use async_ws::http::{
+    offered_protocols, upgrade_request, upgrade_request_with_protocols, HandshakeError,
+    HandshakePolicy,
+};
+use async_ws::message::WsMessage;
+use async_ws::server::{accept, AcceptError};
+use futures::future::join;
+use futures::prelude::*;
+use http::{HeaderValue, Request, Response, StatusCode};
+use smol_timeout::TimeoutExt;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+fn server_config() -> WsConfig {
+    let mut config = WsConfig::server();
+    config.extensions = vec![Box::new(DeflateExtension::default())];
+    config
+}
+
+fn accept_all(_: &Request<()>, _: &mut Response<()>) -> Result<(), Response<()>> {
+    Ok(())
+}
+
+// Writes a raw request and returns the status line of the response.
+async fn raw_request(client: &mut TcpStream, request: &[u8]) -> String {
+    client.write_all(request).await.unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8(response).unwrap();
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[test]
+fn accept_and_echo() {
+    block_on(async {
+        let (server, client) = start_server_and_client_transport().await;
+        let policy = HandshakePolicy::new().allow_origin("https://example.com");
+        let select = |request: &Request<()>, response: &mut Response<()>| {
+            assert_eq!(offered_protocols(request), vec!["v1.example"]);
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static("v1.example"),
+            );
+            Ok(())
+        };
+        let request = upgrade_request_with_protocols(&["v1.example"])
+            .uri("/echo")
+            .header("Host", "localhost")
+            .header("Origin", "https://example.com")
+            .body(())
+            .unwrap();
+        let mut client_config = WsConfig::client();
+        client_config.extensions = vec![Box::new(DeflateExtension::default())];
+        let (accepted, connected) = join(
+            accept(server, server_config(), &policy, select),
+            connect(client, request, client_config),
+        )
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        let (server, request) = accepted.unwrap();
+        let (mut client, response) = connected.unwrap();
+        assert_eq!(request.uri(), "/echo");
+        assert_eq!(response.headers()["Sec-WebSocket-Protocol"], "v1.example");
+        assert_eq!(
+            response.headers()["Sec-WebSocket-Extensions"],
+            "permessage-deflate"
+        );
+        let handshake = server.handshake().unwrap();
+        assert_eq!(handshake.path(), "/echo");
+        assert_eq!(handshake.headers()["Origin"], "https://example.com");
+        assert_eq!(handshake.protocol(), Some("v1.example"));
+        assert_eq!(handshake.extensions(), ["permessage-deflate"]);
+        assert_eq!(client.handshake().unwrap().protocol(), Some("v1.example"));
+        let (receiver, sender) = server.split();
+        assert_eq!(receiver.handshake().unwrap().path(), "/echo");
+        assert_eq!(sender.clone().handshake().unwrap().path(), "/echo");
+        let mut server = receiver.reunite(sender).unwrap();
+        assert_eq!(server.handshake().unwrap().path(), "/echo");
+        let echo = async {
+            if let WsMessage::Text(text) = server.recv().await.unwrap().unwrap() {
+                server.send_text(&text).await.unwrap();
+            }
+        };
+        let (sent, _) = join(client.send_text("hello"), echo)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        sent.unwrap();
+        let echoed = client.recv().timeout(ONE_S).await.unwrap();
+        assert_eq!(
+            echoed.unwrap().unwrap(),
+            WsMessage::Text("hello".to_string())
+        );
+    })
+}
+
+#[test]
+fn origin_rejected() {
+    block_on(async {
+        let (server, mut client) = start_server_and_client_transport().await;
+        let policy = HandshakePolicy::new().allow_origin("https://example.com");
+        let request = upgrade_request()
+            .uri("ws://localhost/")
+            .header("Origin", "https://evil.example")
+            .body(())
+            .unwrap();
+        let (accepted, connected) = join(
+            accept(server, WsConfig::server(), &policy, accept_all),
+            connect(&mut client, request, WsConfig::client()),
+        )
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        match accepted {
+            Err(AcceptError::Handshake(HandshakeError::Origin(Some(origin)))) => {
+                assert_eq!(origin, "https://evil.example")
+            }
+            result => panic!("expected origin error, got: {:?}", result.map(|(_, r)| r)),
+        }
+        match connected {
+            Err(async_ws::client::ConnectError::Handshake(HandshakeError::Status(status))) => {
+                assert_eq!(status, StatusCode::FORBIDDEN)
+            }
+            result => panic!("expected status error, got: {:?}", result.map(|(_, r)| r)),
+        }
+    })
+}
+
+#[test]
+fn callback_rejects() {
+    block_on(async {
+        let (server, mut client) = start_server_and_client_transport().await;
+        let reject = |_: &Request<()>, _: &mut Response<()>| {
+            Err(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header("Content-Length", "0")
+                .body(())
+                .unwrap())
+        };
+        let request = upgrade_request().uri("ws://localhost/").body(()).unwrap();
+        let (accepted, connected) = join(
+            accept(
+                server,
+                WsConfig::server(),
+                &HandshakePolicy::allow_any_origin(),
+                reject,
+            ),
+            connect(&mut client, request, WsConfig::client()),
+        )
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        assert!(matches!(
+            accepted,
+            Err(AcceptError::Rejected(StatusCode::UNAUTHORIZED))
+        ));
+        assert!(connected.is_err());
+    })
+}
+
+#[test]
+fn callback_invalidates_response() {
+    // Each callback amends the response in a way the client would reject.
+    type Amend = fn(&Request<()>, &mut Response<()>) -> Result<(), Response<()>>;
+    let cases: [Amend; 4] = [
+        |_, response| {
+            response.headers_mut().insert(
+                "Sec-WebSocket-Protocol",
+                HeaderValue::from_static("v2.example"),
+            );
+            Ok(())
+        },
+        |_, response| {
+            response.headers_mut().remove("Sec-WebSocket-Accept");
+            Ok(())
+        },
+        |_, response| {
+            response
+                .headers_mut()
+                .insert("Connection", HeaderValue::from_static("close"));
+            Ok(())
+        },
+        |_, response| {
+            response.headers_mut().remove("Sec-WebSocket-Extensions");
+            Ok(())
+        },
+    ];
+    for amend in cases.iter() {
+        block_on(async {
+            let (server, mut client) = start_server_and_client_transport().await;
+            let request = upgrade_request_with_protocols(&["v1.example"])
+                .uri("ws://localhost/")
+                .header("Sec-WebSocket-Extensions", "permessage-deflate")
+                .body(())
+                .unwrap();
+            let (accepted, connected) = join(
+                accept(
+                    server,
+                    server_config(),
+                    &HandshakePolicy::allow_any_origin(),
+                    amend,
+                ),
+                connect(&mut client, request, WsConfig::client()),
+            )
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+            match accepted {
+                Err(AcceptError::InvalidResponse(_)) => {}
+                result => panic!(
+                    "expected invalid response, got: {:?}",
+                    result.map(|(_, r)| r)
+                ),
+            }
+            match connected {
+                Err(async_ws::client::ConnectError::Handshake(HandshakeError::Status(status))) => {
+                    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR)
+                }
+                result => panic!("expected status error, got: {:?}", result.map(|(_, r)| r)),
+            }
+        })
+    }
+}
+
+#[test]
+fn request_limits() {
+    block_on(async {
+        let policy = HandshakePolicy::allow_any_origin()
+            .max_headers(4)
+            .max_head_len(200);
+        let requests: [(&[u8], &str); 4] = [
+            (
+                b"GET / HTTP/1.1\r\na: 1\r\nb: 2\r\nc: 3\r\nd: 4\r\ne: 5\r\n\r\n",
+                "HTTP/1.1 431 Request Header Fields Too Large",
+            ),
+            (&[b'a'; 300], "HTTP/1.1 431 Request Header Fields Too Large"),
+            (b"GET / HTTP/1.1\r\n\r\n", "HTTP/1.1 426 Upgrade Required"),
+            (b"GET /\x01 HTTP/1.1\r\n\r\n", "HTTP/1.1 400 Bad Request"),
+        ];
+        for (request, status) in requests.iter() {
+            let (server, mut client) = start_server_and_client_transport().await;
+            let accepted = async {
+                let result = accept(server, WsConfig::server(), &policy, accept_all).await;
+                assert!(result.is_err());
+            };
+            let (_, status_line) = join(accepted, raw_request(&mut client, request))
+                .timeout(ONE_S)
+                .await
+                .unwrap();
+            assert_eq!(&status_line, status);
+        }
+    })
+}
+
+#[test]
+fn handshake_timeout() {
+    block_on(async {
+        let (server, _client) = start_server_and_client_transport().await;
+        let policy = HandshakePolicy::allow_any_origin().timeout(Some(Duration::from_millis(50)));
+        let accepted = accept(server, WsConfig::server(), &policy, accept_all)
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        assert!(matches!(accepted, Err(AcceptError::Timeout)));
+    })
+}
@@ -0,0 +1,100 @@
+use crate::common::block_on;
+use crate::common::start_server_ws_and_client_transport;
+use async_ws::connection::{WsConfig, WsConnection, WsSender};
+use async_ws::message::{WsMessage, WsMessageKind};
+use futures::future::join3;
+use futures::prelude::*;
+use smol_timeout::TimeoutExt;
+use std::time::Duration;
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+
+#[test]
+fn concurrent_receive_and_send() {
+    block_on(async {
+        let (server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let (mut server_rx, server_tx) = server.split();
+        let (mut client_rx, client_tx) = client.split();
+        let send = |tx: WsSender<_>, payload: &'static [u8]| async move {
+            let mut writer = tx.send(WsMessageKind::Binary).await.unwrap();
+            writer.write_all(payload).await.unwrap();
+            writer.close().await.unwrap();
+        };
+        let receive = async {
+            let mut received = Vec::new();
+            for _ in 0..2 {
+                let mut reader = server_rx.next().await.unwrap();
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).await.unwrap();
+                received.push(buf);
+            }
+            received.sort();
+            received
+        };
+        let (received, _, _) = join3(
+            receive,
+            send(client_tx.clone(), b"a"),
+            send(client_tx.clone(), b"b"),
+        )
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        assert_eq!(received, vec![b"a".to_vec(), b"b".to_vec()]);
+
+        drop(server_tx);
+        let (_, client_close) = future::join(server_rx.next(), client_tx.close(1000, ""))
+            .timeout(ONE_S)
+            .await
+            .unwrap();
+        assert!(client_close.is_ok());
+        assert!(client_rx.next().await.is_none());
+    })
+}
+
+#[test]
+fn reunite() {
+    block_on(async {
+        let (server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let (server_rx, server_tx) = server.split();
+        let (client_rx, client_tx) = client.split();
+        let (server_rx, client_tx) = match server_rx.reunite(client_tx) {
+            Err(err) => (err.0, err.1),
+            Ok(_) => panic!("reunited halves of different connections"),
+        };
+        assert!(server_rx.reunite(server_tx).is_ok());
+        assert!(client_rx.reunite(client_tx).is_ok());
+    })
+}
+
+#[test]
+fn sink() {
+    block_on(async {
+        let (mut server, client) = start_server_ws_and_client_transport(None).await;
+        let client = WsConnection::with_config(client, WsConfig::client());
+        let (_client_rx, mut client_tx) = client.split();
+        let messages = vec![
+            WsMessage::Text("a".to_string()),
+            WsMessage::Binary(vec![]),
+            WsMessage::Binary(vec![1; 4000]),
+        ];
+        let send = async {
+            let mut stream = stream::iter(messages.clone()).map(Ok);
+            client_tx.send_all(&mut stream).await.unwrap();
+            futures::SinkExt::close(&mut client_tx).await.unwrap();
+        };
+        let receive = async {
+            let mut received = Vec::new();
+            while let Some(message) = server.recv().await {
+                received.push(message.unwrap());
+            }
+            received
+        };
+        let (_, received) = future::join(send, receive).timeout(ONE_S).await.unwrap();
+        assert_eq!(received, messages);
+        assert_eq!(server.close_status().unwrap().code, Some(1000));
+    })
+}
@@ -1,85 +1,206 @@
-use crate::common::start_server_ws_and_client_transport;
+use crate::common::{
+    block_on, next_control_frame, no_frame, start_server_ws_with_config_and_client_transport,
+    wait_until, write_pong,
+};
 use async_io::Timer;
-use async_ws::connection::{WsConfig, WsConnection, WsConnectionError};
-use async_ws::frame::{FrameDecoderState, WsControlFrame, WsControlFrameKind, WsFrame};
-use futures::executor::block_on;
+use async_ws::connection::{VirtualClock, WsConfig, WsConnection, WsConnectionError};
+use async_ws::frame::WsControlFrameKind;
 use futures::future::join;
 use futures::prelude::*;
 use futures_lite::future::race;
 use smol_timeout::TimeoutExt;
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::Duration;
 
 mod common;
 
-const ONE_MS: Duration = Duration::from_millis(1);
-const TWO_MS: Duration = Duration::from_millis(2);
-const THREE_MS: Duration = Duration::from_millis(3);
+const ONE_S: Duration = Duration::from_secs(1);
+const TEN_S: Duration = Duration::from_secs(10);
 const TEN_MS: Duration = Duration::from_millis(10);
 
+fn config(clock: &VirtualClock) -> WsConfig {
+    let mut config = WsConfig::server();
+    config.ping_interval = Some(TEN_S);
+    config.pong_timeout = TEN_S;
+    config.clock = Arc::new(clock.clone());
+    config
+}
+
+#[test]
+fn keepalive() {
+    block_on(async {
+        let clock = VirtualClock::new();
+        let (server, mut client) =
+            start_server_ws_with_config_and_client_transport(config(&clock)).await;
+        let (mut server_rx, server_tx) = server.split();
+        let completed = race(
+            async {
+                server_rx.next().await;
+                false
+            },
+            async {
+                let mut latency = None;
+                for rtt in [100, 200, 300].iter() {
+                    clock.advance(TEN_S - Duration::from_millis(1));
+                    no_frame(&mut client).await;
+                    clock.advance(Duration::from_millis(1));
+                    let ping = next_control_frame(&mut client).await;
+                    assert_eq!(ping.kind(), WsControlFrameKind::Ping);
+                    clock.advance(Duration::from_millis(*rtt));
+                    write_pong(&mut client, ping.payload()).await;
+                    wait_until(|| server_tx.latency() != latency).await;
+                    latency = server_tx.latency();
+                }
+                true
+            },
+        )
+        .await;
+        assert!(completed);
+        // Smoothed from round trip times of 100, 200 and 300ms.
+        assert_eq!(server_tx.latency(), Some(Duration::from_nanos(135_937_500)));
+    })
+}
+
+#[test]
+fn keepalive_and_application_ping() {
+    block_on(async {
+        let clock = VirtualClock::new();
+        let (server, mut client) =
+            start_server_ws_with_config_and_client_transport(config(&clock)).await;
+        let (mut server_rx, server_tx) = server.split();
+        let completed = race(
+            async {
+                server_rx.next().await;
+                false
+            },
+            async {
+                clock.advance(TEN_S);
+                let keepalive = next_control_frame(&mut client).await;
+                assert!(!keepalive.payload().is_empty());
+                let mut ping = server_tx.ping(b"");
+                assert!(futures::poll!(&mut ping).is_pending());
+                assert!(next_control_frame(&mut client).await.payload().is_empty());
+                clock.advance(Duration::from_millis(100));
+                write_pong(&mut client, keepalive.payload()).await;
+                wait_until(|| server_tx.latency().is_some()).await;
+                assert!(futures::poll!(&mut ping).is_pending());
+                clock.advance(Duration::from_millis(200));
+                write_pong(&mut client, b"").await;
+                let rtt = ping.timeout(ONE_S).await.unwrap().unwrap();
+                assert_eq!(rtt, Duration::from_millis(300));
+                true
+            },
+        )
+        .await;
+        assert!(completed);
+        assert_eq!(server_tx.latency(), Some(Duration::from_millis(125)));
+    })
+}
+
+// An application ping whose payload looks like a counter is still told apart from keepalive pings.
+#[test]
+fn application_ping_with_counter_payload() {
+    block_on(async {
+        let clock = VirtualClock::new();
+        let (server, mut client) =
+            start_server_ws_with_config_and_client_transport(config(&clock)).await;
+        let (mut server_rx, server_tx) = server.split();
+        let completed = race(
+            async {
+                server_rx.next().await;
+                false
+            },
+            async {
+                clock.advance(TEN_S);
+                let keepalive = next_control_frame(&mut client).await;
+                let mut ping = server_tx.ping(&0u64.to_be_bytes());
+                assert!(futures::poll!(&mut ping).is_pending());
+                let ping_frame = next_control_frame(&mut client).await;
+                assert_ne!(ping_frame.payload(), keepalive.payload());
+                clock.advance(Duration::from_millis(100));
+                write_pong(&mut client, ping_frame.payload()).await;
+                let rtt = ping.timeout(ONE_S).await.unwrap().unwrap();
+                assert_eq!(rtt, Duration::from_millis(100));
+                true
+            },
+        )
+        .await;
+        assert!(completed);
+    })
+}
+
 #[test]
-fn idle_keepalive() {
+fn keepalive_between_connections() {
     block_on(async {
-        let (mut server, client) = start_server_ws_and_client_transport(Some(ONE_MS)).await;
+        let clock = VirtualClock::new();
+        let (server, client) =
+            start_server_ws_with_config_and_client_transport(config(&clock)).await;
         let mut client = WsConnection::with_config(client, WsConfig::client());
-        let result = join(server.next(), client.next()).timeout(TEN_MS).await;
-        assert!(result.is_none())
+        let (mut server_rx, server_tx) = server.split();
+        let completed = race(
+            async {
+                future::join(server_rx.next(), client.next()).await;
+                false
+            },
+            async {
+                clock.advance(TEN_S);
+                wait_until(|| server_tx.latency().is_some()).await;
+                clock.advance(TEN_S);
+                Timer::after(TEN_MS).await;
+                true
+            },
+        )
+        .await;
+        assert!(completed);
+        assert!(server_tx.err().is_none());
     })
 }
 
 #[test]
-fn idle_keepalive_failure() {
+fn keepalive_failure() {
     block_on(async {
-        let (mut server, client) = start_server_ws_and_client_transport(Some(ONE_MS)).await;
-        let result = server.next().timeout(TEN_MS).await;
-        drop(client);
-        if let Some(None) = result {
-            if let Some(WsConnectionError::Timeout) = server.err().as_deref() {
-                return;
-            }
-            panic!("expected timeout error, got: {:?}", server.err())
+        let clock = VirtualClock::new();
+        let (mut server, mut client) =
+            start_server_ws_with_config_and_client_transport(config(&clock)).await;
+        let completed = race(
+            async {
+                server.next().await;
+                true
+            },
+            async {
+                clock.advance(TEN_S);
+                let ping = next_control_frame(&mut client).await;
+                assert_eq!(ping.kind(), WsControlFrameKind::Ping);
+                clock.advance(TEN_S);
+                future::pending::<()>().timeout(ONE_S).await;
+                false
+            },
+        )
+        .await;
+        assert!(completed, "expected end of stream");
+        match server.err().as_deref() {
+            Some(WsConnectionError::Timeout) => {}
+            err => panic!("expected timeout error, got: {:?}", err),
         }
-        panic!("expected end of stream")
     })
 }
 
 #[test]
-fn idle_keepalive_timing() {
+fn keepalive_disabled() {
     block_on(async {
-        let (mut server, mut client) = start_server_ws_and_client_transport(Some(TWO_MS)).await;
-        let mut start = Instant::now();
+        let clock = VirtualClock::new();
+        let mut config = config(&clock);
+        config.ping_interval = None;
+        let (mut server, mut client) =
+            start_server_ws_with_config_and_client_transport(config).await;
         let completed = race(
             async {
                 server.next().await;
                 false
             },
             async {
-                for _ in 0..10 {
-                    let frame = FrameDecoderState::new()
-                        .restore(&mut client)
-                        .await
-                        .unwrap()
-                        .1;
-                    let frame = match frame {
-                        WsFrame::Control(frame) => frame,
-                        WsFrame::Data(_) => panic!("unexpected data frame"),
-                    };
-                    let frame_kind = frame.kind();
-                    if frame_kind != WsControlFrameKind::Ping {
-                        panic!("unexpected control frame kind: {:?}", frame_kind)
-                    }
-                    let elapsed = start.elapsed();
-                    if elapsed < TWO_MS {
-                        panic!("ping arrived early: {:?}", elapsed)
-                    }
-                    if elapsed > THREE_MS {
-                        panic!("ping arrived late: {:?}", elapsed)
-                    }
-                    Timer::after(ONE_MS).await;
-                    let pong = WsControlFrame::new(WsControlFrameKind::Pong, frame.payload());
-                    let pong_buffer = WsFrame::encode_vec(pong.head([1, 1, 1, 1]), pong.payload());
-                    client.write_all(&pong_buffer).await.unwrap();
-                    start = Instant::now();
-                }
+                clock.advance(Duration::from_secs(3600));
+                no_frame(&mut client).await;
                 true
             },
         )
@@ -87,3 +208,60 @@ fn idle_keepalive_timing() {
         assert!(completed);
     })
 }
+
+#[test]
+fn idle_timeout() {
+    block_on(async {
+        let clock = VirtualClock::new();
+        let mut config = config(&clock);
+        config.ping_interval = None;
+        config.idle_timeout = Some(TEN_S);
+        let (mut server, mut client) =
+            start_server_ws_with_config_and_client_transport(config).await;
+        let close = race(
+            async {
+                server.next().await;
+                None
+            },
+            async {
+                clock.advance(TEN_S - Duration::from_millis(1));
+                no_frame(&mut client).await;
+                clock.advance(Duration::from_millis(1));
+                Some(next_control_frame(&mut client).await)
+            },
+        )
+        .await
+        .expect("server stream ended");
+        assert_eq!(close.kind(), WsControlFrameKind::Close);
+        assert_eq!(&close.payload()[..2], &1001u16.to_be_bytes());
+    })
+}
+
+#[test]
+fn close_timeout() {
+    block_on(async {
+        let clock = VirtualClock::new();
+        let mut config = config(&clock);
+        config.ping_interval = None;
+        let (server, mut client) = start_server_ws_with_config_and_client_transport(config).await;
+        let close = server.close(1000, "");
+        let (closed, ()) = join(close, async {
+            let close = next_control_frame(&mut client).await;
+            assert_eq!(close.kind(), WsControlFrameKind::Close);
+            clock.advance(TEN_S - Duration::from_millis(1));
+            no_frame(&mut client).await;
+            clock.advance(Duration::from_millis(1));
+        })
+        .timeout(ONE_S)
+        .await
+        .unwrap();
+        match closed.as_ref().map_err(|err| &**err) {
+            Err(WsConnectionError::Timeout) => {}
+            closed => panic!("expected timeout error, got: {:?}", closed),
+        }
+        // The transport was shut down.
+        let mut buf = [0u8; 16];
+        let read = client.read(&mut buf).timeout(ONE_S).await.unwrap();
+        assert_eq!(read.unwrap(), 0);
+    })
+}
@@ -0,0 +1,153 @@
+#![cfg(feature = "tokio")]
+
+use crate::common::{next_control_frame, wait_until, write_pong};
+use async_ws::connection::{TokioClock, WsConfig, WsConnection, WsConnectionError};
+use async_ws::frame::WsControlFrameKind;
+use async_ws::message::{WsMessage, WsMessageKind};
+use futures::future::join;
+use futures::prelude::*;
+use futures_lite::future::race;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Instant};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+mod common;
+
+const ONE_S: Duration = Duration::from_secs(1);
+const INTERVAL: Duration = Duration::from_millis(50);
+
+async fn start_server_ws_with_config_and_client_transport(
+    server_config: WsConfig,
+) -> (WsConnection<Compat<TcpStream>>, Compat<TcpStream>) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (server, client) = future::join(
+        listener.accept(),
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)),
+    )
+    .await;
+    let server = WsConnection::from_tokio(server.unwrap().0, server_config);
+    (server, client.unwrap().compat())
+}
+
+#[tokio::test]
+async fn tokio_io_traits() {
+    let (mut server, client) =
+        start_server_ws_with_config_and_client_transport(WsConfig::server()).await;
+    let mut client_config = WsConfig::client();
+    client_config.clock = Arc::new(TokioClock);
+    let client = WsConnection::with_config(client, client_config);
+    let send = async {
+        let mut writer = client.send(WsMessageKind::Text).await.unwrap();
+        // Uses the tokio io traits, the futures io traits are implemented as well.
+        tokio::io::AsyncWriteExt::write_all(&mut writer, b"hello")
+            .await
+            .unwrap();
+        tokio::io::AsyncWriteExt::shutdown(&mut writer)
+            .await
+            .unwrap();
+    };
+    let receive = async {
+        let mut reader = server.next().await.unwrap();
+        let mut text = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut reader, &mut text)
+            .await
+            .unwrap();
+        text
+    };
+    let (_, text) = timeout(ONE_S, future::join(send, receive)).await.unwrap();
+    assert_eq!(text, "hello");
+    let (_, echoed) = timeout(
+        ONE_S,
+        future::join(server.send_text("world"), async {
+            let mut client = client;
+            client.recv().await
+        }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        echoed.unwrap().unwrap(),
+        WsMessage::Text("world".to_string())
+    );
+}
+
+// Keepalive pings, pong and idle timeouts in real time on the tokio clock. Only lower bounds are
+// checked, so slow test machines can't cause spurious failures.
+fn timeout_config() -> WsConfig {
+    let mut config = WsConfig::server();
+    config.ping_interval = Some(INTERVAL);
+    config.pong_timeout = INTERVAL;
+    config.clock = Arc::new(TokioClock);
+    config
+}
+
+#[tokio::test]
+async fn tokio_keepalive() {
+    let mut start = Instant::now();
+    let (server, mut client) =
+        start_server_ws_with_config_and_client_transport(timeout_config()).await;
+    let (mut server_rx, server_tx) = server.split();
+    let completed = race(
+        async {
+            server_rx.next().await;
+            false
+        },
+        async {
+            for _ in 0..2 {
+                let ping = next_control_frame(&mut client).await;
+                assert_eq!(ping.kind(), WsControlFrameKind::Ping);
+                assert!(start.elapsed() >= INTERVAL);
+                start = Instant::now();
+                write_pong(&mut client, ping.payload()).await;
+            }
+            wait_until(|| server_tx.latency().is_some()).await;
+            true
+        },
+    )
+    .await;
+    assert!(completed);
+    assert!(server_tx.err().is_none());
+}
+
+#[tokio::test]
+async fn tokio_keepalive_failure() {
+    let (mut server, mut client) =
+        start_server_ws_with_config_and_client_transport(timeout_config()).await;
+    let (next, ping_received) = join(timeout(ONE_S, server.next()), async {
+        let ping = next_control_frame(&mut client).await;
+        assert_eq!(ping.kind(), WsControlFrameKind::Ping);
+        Instant::now()
+    })
+    .await;
+    assert!(next.unwrap().is_none());
+    assert!(ping_received.elapsed() >= INTERVAL);
+    match server.err().as_deref() {
+        Some(WsConnectionError::Timeout) => {}
+        err => panic!("expected timeout error, got: {:?}", err),
+    }
+}
+
+#[tokio::test]
+async fn tokio_idle_timeout() {
+    let start = Instant::now();
+    let mut config = timeout_config();
+    config.ping_interval = None;
+    config.idle_timeout = Some(INTERVAL);
+    let (mut server, mut client) = start_server_ws_with_config_and_client_transport(config).await;
+    let close = race(
+        async {
+            server.next().await;
+            None
+        },
+        async { Some(next_control_frame(&mut client).await) },
+    )
+    .await
+    .expect("server stream ended");
+    assert!(start.elapsed() >= INTERVAL);
+    assert_eq!(close.kind(), WsControlFrameKind::Close);
+    assert_eq!(&close.payload()[..2], &1001u16.to_be_bytes());
+}